@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use crate::elr_pilot::*;
+use crate::sentences::LanguageSlices;
+use crate::syntax_tree::SyntaxTree;
+
+/// Number of trees we look for: two are enough to witness ambiguity.
+const MAX_TREES: usize = 2;
+
+/// How many times the same parsing subproblem may be nested within itself.
+/// Allowing one re-entry exposes nets where a nonterminal derives itself
+/// without consuming input.
+const MAX_REENTRY: usize = 2;
+
+pub struct AmbiguousSentence {
+    pub sentence: String,
+    pub trees: Vec<SyntaxTree>
+}
+
+/// Enumerates the sentences of the net up to `max_len` terminals and
+/// returns those having at least two distinct syntax trees.
+pub fn find_ambiguities(net: &MachineNet, max_len: usize) -> Vec<AmbiguousSentence> {
+    let slices = LanguageSlices::new(net, max_len);
+    slices.sentences().into_iter().filter_map(|sentence| {
+        let trees = syntax_trees(net, &sentence, MAX_TREES);
+        if trees.len() >= 2 {
+            Some(AmbiguousSentence{sentence, trees})
+        } else {
+            None
+        }
+    }).collect()
+}
+
+/// Up to `limit` distinct syntax trees of `sentence` rooted in the axiom.
+pub fn syntax_trees(net: &MachineNet, sentence: &str, limit: usize) -> Vec<SyntaxTree> {
+    let mut counter = TreeCounter{
+        net,
+        word: sentence.chars().collect(),
+        limit,
+        memo: HashMap::new(),
+        active: HashMap::new()
+    };
+    let n = counter.word.len();
+    counter.trees(net.axiom, 0, n).0
+}
+
+pub fn print_ambiguities(net: &MachineNet, max_len: usize) {
+    let found = find_ambiguities(net, max_len);
+    for amb in &found {
+        let sentence = if amb.sentence.is_empty() { "ε" } else { &amb.sentence };
+        println!("ambiguous sentence: {}", sentence);
+        for (i, t) in amb.trees.iter().enumerate() {
+            println!("  tree {}: {}", i + 1, t);
+        }
+    }
+    if found.is_empty() {
+        println!("no ambiguous sentence up to length {}", max_len);
+    }
+}
+
+type Key = (char, i32, usize, usize);
+
+struct TreeCounter<'a> {
+    net: &'a MachineNet,
+    word: Vec<char>,
+    limit: usize,
+    memo: HashMap<Key, Vec<Vec<SyntaxTree>>>,
+    active: HashMap<Key, usize>
+}
+
+impl TreeCounter<'_> {
+    fn trees(&mut self, machine: char, i: usize, j: usize) -> (Vec<SyntaxTree>, bool) {
+        let (paths, cut) = self.paths(machine, self.net.initial_id(machine), i, j);
+        let trees = paths.into_iter().map(|children| SyntaxTree::Node(machine, children)).collect();
+        (trees, cut)
+    }
+
+    /// Sequences of children read by `machine` going from state `id` to a
+    /// final state while consuming `word[i..j]`. The flag tells whether
+    /// the search was cut short by a nested occurrence of itself, in which
+    /// case the result is not memoized.
+    fn paths(&mut self, machine: char, id: i32, i: usize, j: usize) -> (Vec<Vec<SyntaxTree>>, bool) {
+        let key = (machine, id, i, j);
+        if let Some(res) = self.memo.get(&key) {
+            return (res.clone(), false);
+        }
+        let depth = self.active.get(&key).copied().unwrap_or(0);
+        if depth >= MAX_REENTRY {
+            return (vec![], true);
+        }
+        self.active.insert(key, depth + 1);
+
+        let mut res: Vec<Vec<SyntaxTree>> = Vec::new();
+        let mut cut = false;
+        let net = self.net;
+        let state = net.lookup_state(machine, id);
+        if i == j && state.is_final {
+            res.push(vec![]);
+        }
+        for t in &state.transitions {
+            if !t.is_nonterminal() {
+                if i < j && self.word[i] == t.character {
+                    let (rest, rest_cut) = self.paths(machine, t.dest_id, i + 1, j);
+                    cut |= rest_cut;
+                    for r in rest {
+                        let mut path = vec![SyntaxTree::Leaf(t.character)];
+                        path.extend(r);
+                        res.push(path);
+                    }
+                }
+                continue;
+            }
+            for k in i..=j {
+                let (subtrees, sub_cut) = self.trees(t.character, i, k);
+                cut |= sub_cut;
+                if subtrees.is_empty() {
+                    continue;
+                }
+                let (rest, rest_cut) = self.paths(machine, t.dest_id, k, j);
+                cut |= rest_cut;
+                for sub in &subtrees {
+                    for r in &rest {
+                        let mut path = vec![sub.clone()];
+                        path.extend(r.iter().cloned());
+                        res.push(path);
+                    }
+                }
+            }
+        }
+        let mut unique: Vec<Vec<SyntaxTree>> = Vec::new();
+        for path in res {
+            if !unique.contains(&path) {
+                unique.push(path);
+            }
+        }
+        let mut res = unique;
+        res.truncate(self.limit);
+
+        self.active.insert(key, depth);
+        if !cut {
+            self.memo.insert(key, res.clone());
+        }
+        (res, cut)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    #[test]
+    fn test_find_ambiguities() {
+        let net = load_net(r"./tests/dangling_else.txt").unwrap();
+        let found = find_ambiguities(&net, 6);
+        assert!(!found.is_empty());
+        for amb in &found {
+            assert_ne!(amb.trees[0], amb.trees[1]);
+            assert!(amb.trees.iter().all(|t| t.frontier() == amb.sentence));
+        }
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        assert!(find_ambiguities(&net, 6).is_empty());
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use crate::elr_pilot::*;
+
+/// Nullability, initials and follow sets of a machine net, computed once
+/// for the whole net by fixed-point iteration.
+#[derive(Debug)]
+pub struct NetAnalysis {
+    /// Initial state of every machine.
+    pub initial_ids: HashMap<char, i32>,
+    pub nullable_states: HashSet<(char, i32)>,
+    pub initials: HashMap<(char, i32), HashSet<char>>,
+    pub follow: HashMap<char, HashSet<char>>
+}
+
+impl NetAnalysis {
+    pub fn new(net: &MachineNet) -> NetAnalysis {
+        let nullable_states = compute_nullable_states(net);
+        let initials = compute_initials(net, &nullable_states);
+        let follow = compute_follow(net, &nullable_states, &initials);
+        let initial_ids = net.machines.iter().map(|m| (m.name, m.initial_id())).collect();
+        NetAnalysis{initial_ids, nullable_states, initials, follow}
+    }
+
+    pub fn is_nullable(&self, machine: char) -> bool {
+        self.is_state_nullable(machine, self.initial_ids[&machine])
+    }
+
+    /// A state is nullable when a final state is reachable from it through
+    /// nullable nonterminal arcs only.
+    pub fn is_state_nullable(&self, machine: char, id: i32) -> bool {
+        self.nullable_states.contains(&(machine, id))
+    }
+
+    pub fn nullable_machines(&self, net: &MachineNet) -> Vec<char> {
+        net.machines.iter().map(|m| m.name).filter(|&m| self.is_nullable(m)).collect()
+    }
+
+    pub fn initials(&self, machine: char, id: i32) -> &HashSet<char> {
+        &self.initials[&(machine, id)]
+    }
+
+    pub fn follow(&self, machine: char) -> &HashSet<char> {
+        &self.follow[&machine]
+    }
+
+    /// Lookaheads of the candidates spawned by a nonterminal arc reaching
+    /// state `id`, when `next` is the lookahead of the calling candidate.
+    pub fn lookaheads(&self, machine: char, id: i32, next: char) -> HashSet<char> {
+        let mut res = self.initials(machine, id).clone();
+        if self.is_state_nullable(machine, id) {
+            res.insert(next);
+        }
+        res
+    }
+
+    pub fn print_report(&self, net: &MachineNet) {
+        let nullable = self.nullable_machines(net).iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        println!("nullable: {{{}}}", nullable.join(", "));
+        println!();
+        for m in &net.machines {
+            for s in &m.states {
+                println!("Ini({}{}) = {}", s.name, m.name, set_to_string(self.initials(m.name, s.id)));
+            }
+        }
+        println!();
+        for m in &net.machines {
+            println!("Follow({}) = {}", m.name, set_to_string(self.follow(m.name)));
+        }
+    }
+}
+
+pub fn set_to_string(set: &HashSet<char>) -> String {
+    let mut chars: Vec<_> = set.iter().collect();
+    chars.sort();
+    let strs: Vec<_> = chars.into_iter().map(|c| c.to_string()).collect();
+    format!("{{{}}}", strs.join(", "))
+}
+
+/// Concatenation of two sets of lookahead strings truncated to `k`
+/// terminals. Strings of `left` that are already `k` long, or that end with
+/// the end marker, are not extended.
+pub fn concat_k(k: usize, end_marker: char, left: &HashSet<String>, right: &HashSet<String>) -> HashSet<String> {
+    let mut res: HashSet<String> = HashSet::new();
+    for x in left {
+        if x.chars().count() >= k || x.ends_with(end_marker) {
+            res.insert(x.chars().take(k).collect());
+            continue;
+        }
+        for y in right {
+            res.insert(x.chars().chain(y.chars()).take(k).collect());
+        }
+    }
+    res
+}
+
+/// Initials and follow sets generalized to strings of up to `k` terminals.
+/// `Ini_k` of a state holds the `k`-prefixes of the strings leading from
+/// it to a final state; shorter strings are whole strings.
+#[derive(Debug)]
+pub struct KNetAnalysis {
+    pub k: usize,
+    pub end_marker: char,
+    pub initials: HashMap<(char, i32), HashSet<String>>,
+    pub follow: HashMap<char, HashSet<String>>
+}
+
+impl KNetAnalysis {
+    pub fn new(net: &MachineNet, k: usize) -> KNetAnalysis {
+        let initials = compute_initials_k(net, k);
+        let follow = compute_follow_k(net, k, &initials);
+        KNetAnalysis{k, end_marker: net.end_marker, initials, follow}
+    }
+
+    pub fn initials(&self, machine: char, id: i32) -> &HashSet<String> {
+        &self.initials[&(machine, id)]
+    }
+
+    pub fn follow(&self, machine: char) -> &HashSet<String> {
+        &self.follow[&machine]
+    }
+
+    /// `k`-lookaheads of the candidates spawned by a nonterminal arc
+    /// reaching state `id`, when `next` is the lookahead of the caller.
+    pub fn lookaheads(&self, machine: char, id: i32, next: &str) -> HashSet<String> {
+        concat_k(self.k, self.end_marker, self.initials(machine, id), &HashSet::from([next.to_string()]))
+    }
+
+    pub fn print_report(&self, net: &MachineNet) {
+        for m in &net.machines {
+            for s in &m.states {
+                println!("Ini_{}({}{}) = {}", self.k, s.name, m.name, strings_to_string(self.initials(m.name, s.id)));
+            }
+        }
+        println!();
+        for m in &net.machines {
+            println!("Follow_{}({}) = {}", self.k, m.name, strings_to_string(self.follow(m.name)));
+        }
+    }
+}
+
+pub fn strings_to_string(set: &HashSet<String>) -> String {
+    let mut strs: Vec<_> = set.iter().map(|s| if s.is_empty() { "ε" } else { s.as_str() }).collect();
+    strs.sort();
+    format!("{{{}}}", strs.join(", "))
+}
+
+fn compute_initials_k(net: &MachineNet, k: usize) -> HashMap<(char, i32), HashSet<String>> {
+    let mut res: HashMap<(char, i32), HashSet<String>> = HashMap::new();
+    for m in &net.machines {
+        for s in &m.states {
+            let init = if s.is_final { HashSet::from([String::new()]) } else { HashSet::new() };
+            res.insert((m.name, s.id), init);
+        }
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            for s in &m.states {
+                let mut new: HashSet<String> = HashSet::new();
+                for t in &s.transitions {
+                    let head = if t.is_nonterminal() {
+                        res[&(t.character, net.initial_id(t.character))].clone()
+                    } else {
+                        HashSet::from([t.character.to_string()])
+                    };
+                    new.extend(concat_k(k, net.end_marker, &head, &res[&(m.name, t.dest_id)]));
+                }
+                let cur = res.get_mut(&(m.name, s.id)).unwrap();
+                let old_len = cur.len();
+                cur.extend(new);
+                changed |= cur.len() != old_len;
+            }
+        }
+    }
+    res
+}
+
+fn compute_follow_k(net: &MachineNet, k: usize, initials: &HashMap<(char, i32), HashSet<String>>) -> HashMap<char, HashSet<String>> {
+    let mut res: HashMap<char, HashSet<String>> = net.machines.iter().map(|m| (m.name, HashSet::new())).collect();
+    res.get_mut(&net.axiom).unwrap().insert(net.end_marker.to_string());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            for s in &m.states {
+                for t in s.transitions.iter().filter(|t| t.is_nonterminal()) {
+                    let new = concat_k(k, net.end_marker, &initials[&(m.name, t.dest_id)], &res[&m.name]);
+                    let cur = res.get_mut(&t.character).unwrap();
+                    let old_len = cur.len();
+                    cur.extend(new);
+                    changed |= cur.len() != old_len;
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Shortest terminal string leading from each machine state to a final
+/// state of the same machine. States that cannot complete have no entry.
+pub fn shortest_strings(net: &MachineNet) -> HashMap<(char, i32), String> {
+    let mut res: HashMap<(char, i32), String> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            for s in &m.states {
+                let mut best = if s.is_final { Some(String::new()) } else { None };
+                for t in &s.transitions {
+                    let head = if t.is_nonterminal() {
+                        res.get(&(t.character, net.initial_id(t.character))).cloned()
+                    } else {
+                        Some(t.character.to_string())
+                    };
+                    let (Some(head), Some(tail)) = (head, res.get(&(m.name, t.dest_id))) else {
+                        continue;
+                    };
+                    let cand = head + tail;
+                    if best.as_ref().is_none_or(|b| cand.len() < b.len()) {
+                        best = Some(cand);
+                    }
+                }
+                if let Some(best) = best {
+                    if res.get(&(m.name, s.id)).is_none_or(|cur| best.len() < cur.len()) {
+                        res.insert((m.name, s.id), best);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Like `shortest_strings`, for each terminal the string can start with:
+/// the entry of `(machine, state, a)` is the shortest string leading to a
+/// final state whose first terminal is `a`.
+pub fn shortest_strings_by_first(net: &MachineNet, strings: &HashMap<(char, i32), String>) -> HashMap<(char, i32, char), String> {
+    let mut res: HashMap<(char, i32, char), String> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            for s in &m.states {
+                for t in &s.transitions {
+                    let Some(tail) = strings.get(&(m.name, t.dest_id)) else {
+                        continue;
+                    };
+                    let mut found: Vec<(char, String)> = Vec::new();
+                    if !t.is_nonterminal() {
+                        found.push((t.character, t.character.to_string() + tail));
+                    } else {
+                        let initial = net.initial_id(t.character);
+                        for ((callee, id, a), head) in &res {
+                            if *callee == t.character && *id == initial {
+                                found.push((*a, head.clone() + tail));
+                            }
+                        }
+                        if strings.get(&(t.character, initial)).is_some_and(|x| x.is_empty()) {
+                            for ((machine, id, a), rest) in &res {
+                                if *machine == m.name && *id == t.dest_id {
+                                    found.push((*a, rest.clone()));
+                                }
+                            }
+                        }
+                    }
+                    for (a, cand) in found {
+                        if res.get(&(m.name, s.id, a)).is_none_or(|cur| cand.len() < cur.len()) {
+                            res.insert((m.name, s.id, a), cand);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+fn compute_nullable_states(net: &MachineNet) -> HashSet<(char, i32)> {
+    let mut res: HashSet<(char, i32)> = HashSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            for s in &m.states {
+                if res.contains(&(m.name, s.id)) {
+                    continue;
+                }
+                let nullable = s.is_final || s.transitions.iter().any(|t| {
+                    t.is_nonterminal() && res.contains(&(t.character, net.initial_id(t.character))) && res.contains(&(m.name, t.dest_id))
+                });
+                if nullable {
+                    res.insert((m.name, s.id));
+                    changed = true;
+                }
+            }
+        }
+    }
+    res
+}
+
+fn compute_initials(net: &MachineNet, nullable: &HashSet<(char, i32)>) -> HashMap<(char, i32), HashSet<char>> {
+    let mut res: HashMap<(char, i32), HashSet<char>> = HashMap::new();
+    for m in &net.machines {
+        for s in &m.states {
+            let terminals = s.transitions.iter().filter(|t| !t.is_nonterminal()).map(|t| t.character);
+            res.insert((m.name, s.id), terminals.collect());
+        }
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            for s in &m.states {
+                let mut new: HashSet<char> = HashSet::new();
+                for t in s.transitions.iter().filter(|t| t.is_nonterminal()) {
+                    let entry = (t.character, net.initial_id(t.character));
+                    new.extend(&res[&entry]);
+                    if nullable.contains(&entry) {
+                        new.extend(&res[&(m.name, t.dest_id)]);
+                    }
+                }
+                let cur = res.get_mut(&(m.name, s.id)).unwrap();
+                let old_len = cur.len();
+                cur.extend(new);
+                changed |= cur.len() != old_len;
+            }
+        }
+    }
+    res
+}
+
+fn compute_follow(net: &MachineNet, nullable: &HashSet<(char, i32)>, initials: &HashMap<(char, i32), HashSet<char>>) -> HashMap<char, HashSet<char>> {
+    let mut res: HashMap<char, HashSet<char>> = net.machines.iter().map(|m| (m.name, HashSet::new())).collect();
+    res.get_mut(&net.axiom).unwrap().insert(net.end_marker);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            for s in &m.states {
+                for t in s.transitions.iter().filter(|t| t.is_nonterminal()) {
+                    let mut new = initials[&(m.name, t.dest_id)].clone();
+                    if nullable.contains(&(m.name, t.dest_id)) {
+                        new.extend(&res[&m.name]);
+                    }
+                    let cur = res.get_mut(&t.character).unwrap();
+                    let old_len = cur.len();
+                    cur.extend(new);
+                    changed |= cur.len() != old_len;
+                }
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use crate::load_net;
+    use super::NetAnalysis;
+
+    #[test]
+    fn test_net_analysis() {
+        let net = load_net(r"./tests/elr_mnet_2020-01-14.txt").unwrap();
+        let an = NetAnalysis::new(&net);
+        assert_eq!(an.nullable_machines(&net), vec!['S', 'A']);
+        assert!(an.is_state_nullable('S', 2));
+        assert!(an.is_state_nullable('S', 1));
+        assert_eq!(an.initials('S', 1), &HashSet::from(['a']));
+        assert_eq!(an.initials('S', 3), &HashSet::new());
+        assert_eq!(an.follow('S'), &HashSet::from(['$', 'a']));
+        assert_eq!(an.follow('A'), &HashSet::from(['$', 'a']));
+    }
+}
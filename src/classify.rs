@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use crate::ambiguity::find_ambiguities;
+use crate::analysis::NetAnalysis;
+use crate::elr_pilot::*;
+use crate::regular::find_self_embeddings;
+
+/// Longest sentence searched for a second syntax tree.
+const AMBIGUITY_BOUND: usize = 6;
+
+/// Whether the net belongs to a class, and the facts that decided it.
+pub struct Verdict {
+    pub class: &'static str,
+    pub holds: bool,
+    pub evidence: Vec<String>
+}
+
+impl Verdict {
+    pub fn print(&self) {
+        println!("{}: {}", self.class, if self.holds { "yes" } else { "no" });
+        for e in &self.evidence {
+            println!("  {}", e);
+        }
+    }
+}
+
+fn conflict_lines(pilot: &Pilot, net: &MachineNet, sr: &[ShiftReduceConflict], rr: &[ReduceReduceConflict], c: &[ConvergenceConflict]) -> Vec<String> {
+    let cand = |s: i32, i: usize| pilot.lookup_state(s).candidates[i].describe(net);
+    let mut res: Vec<String> = Vec::new();
+    for x in sr {
+        res.push(format!("shift-reduce conflict in I{} between {} and '{}'", x.state_id, cand(x.state_id, x.candidate_idx), x.edge));
+    }
+    for x in rr {
+        res.push(format!("reduce-reduce conflict in I{} between {} and {}", x.state_id, cand(x.state_id, x.candidate_1_idx), cand(x.state_id, x.candidate_2_idx)));
+    }
+    for x in c {
+        res.push(format!("convergence conflict on I{} -{}-> I{} between {} and {}", x.state_1_id, x.transition_char, x.state_2_id, cand(x.state_1_id, x.candidate_1_1_idx), cand(x.state_1_id, x.candidate_1_2_idx)));
+    }
+    res
+}
+
+fn pilot_conflicts(pilot: &Pilot, net: &MachineNet) -> Vec<String> {
+    pilot.states.iter().flat_map(|s| {
+        conflict_lines(pilot, net, &s.shift_reduce_conflicts(), &s.reduce_reduce_conflicts(), &s.convergence_conflicts())
+    }).collect()
+}
+
+fn lr0_conflicts(pilot: &Pilot, net: &MachineNet) -> Vec<String> {
+    pilot.states.iter().flat_map(|s| {
+        conflict_lines(pilot, net, &s.lr0_shift_reduce_conflicts(None), &s.lr0_reduce_reduce_conflicts(None), &s.convergence_conflicts())
+    }).collect()
+}
+
+fn regular_verdict(net: &MachineNet) -> Verdict {
+    let found = find_self_embeddings(net);
+    let evidence = if found.is_empty() {
+        vec!["no machine embeds itself with terminals on both sides".to_string()]
+    } else {
+        found.iter().map(|e| e.describe(net)).collect()
+    };
+    Verdict{class: "regular", holds: found.is_empty(), evidence}
+}
+
+/// For every machine that can call itself before reading a terminal, the
+/// arc closing the loop.
+fn left_recursions(net: &MachineNet, analysis: &NetAnalysis) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    for m in &net.machines {
+        let mut seen: HashSet<(char, i32)> = HashSet::from([(m.name, m.initial_id())]);
+        let mut work = vec![(m.name, m.initial_id())];
+        'search: while let Some((machine, id)) = work.pop() {
+            for t in net.lookup_state(machine, id).transitions.iter().filter(|t| t.is_nonterminal()) {
+                if t.character == m.name {
+                    res.push(format!("machine {} is left-recursive through arc {} -{}-> {}", m.name, net.state_label(machine, id), t.character, net.state_label(machine, t.dest_id)));
+                    break 'search;
+                }
+                let mut next = vec![(t.character, net.initial_id(t.character))];
+                if analysis.is_nullable(t.character) {
+                    next.push((machine, t.dest_id));
+                }
+                for n in next {
+                    if seen.insert(n) {
+                        work.push(n);
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+/// ELL(1) holds when the pilot has no conflicts, every transition shifts
+/// candidates of a single machine state and no machine is left-recursive.
+fn ell_verdict(pilot: &Pilot, net: &MachineNet, conflicts: &[String]) -> Verdict {
+    let mut evidence = left_recursions(net, &NetAnalysis::new(net));
+    for s in &pilot.states {
+        for t in s.transitions.iter().filter(|t| t.multiplicity > 1) {
+            evidence.push(format!("I{} -{}-> I{} has multiplicity {}", s.id, t.character, t.dest_id, t.multiplicity));
+        }
+    }
+    let holds = evidence.is_empty() && conflicts.is_empty();
+    if !conflicts.is_empty() {
+        let plural = if conflicts.len() == 1 { "" } else { "s" };
+        evidence.push(format!("the pilot has {} conflict{}", conflicts.len(), plural));
+    }
+    if holds {
+        evidence.push("every pilot transition has multiplicity 1, there are no conflicts and no left recursion".to_string());
+    }
+    Verdict{class: "ELL(1)", holds, evidence}
+}
+
+fn conflict_verdict(class: &'static str, conflicts: Vec<String>) -> Verdict {
+    let holds = conflicts.is_empty();
+    let evidence = if holds { vec!["no conflicts".to_string()] } else { conflicts };
+    Verdict{class, holds, evidence}
+}
+
+fn ambiguity_verdict(net: &MachineNet, elr: bool) -> Verdict {
+    if elr {
+        let evidence = vec!["ELR(1) nets are unambiguous".to_string()];
+        return Verdict{class: "ambiguous", holds: false, evidence};
+    }
+    match find_ambiguities(net, AMBIGUITY_BOUND).first() {
+        Some(amb) => {
+            let sentence = if amb.sentence.is_empty() { "ε" } else { &amb.sentence };
+            let mut evidence = vec![format!("sentence {} has two syntax trees:", sentence)];
+            evidence.extend(amb.trees.iter().map(|t| format!("  {}", t)));
+            Verdict{class: "ambiguous", holds: true, evidence}
+        }
+        None => {
+            let evidence = vec![format!("suspect: not ELR(1), but no ambiguous sentence up to length {}", AMBIGUITY_BOUND)];
+            Verdict{class: "ambiguous", holds: false, evidence}
+        }
+    }
+}
+
+/// Checks the classes of the net from the broadest structural property to
+/// the strictest deterministic one, each with its evidence.
+pub fn classify(net: &MachineNet) -> Vec<Verdict> {
+    let pilot = create_pilot(net);
+    let conflicts = pilot_conflicts(&pilot, net);
+    let ell = ell_verdict(&pilot, net, &conflicts);
+    let elr = conflict_verdict("ELR(1)", conflicts);
+    let lr0 = conflict_verdict("LR(0)", lr0_conflicts(&create_lr0_pilot(net), net));
+    let ambiguous = ambiguity_verdict(net, elr.holds);
+    vec![regular_verdict(net), ell, elr, lr0, ambiguous]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    fn holds(net: &str) -> Vec<bool> {
+        let net = load_net(net).unwrap();
+        classify(&net).iter().map(|v| v.holds).collect()
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(holds(r"./tests/elr_mnet_2024-06-13.txt"), vec![false, true, true, false, false]);
+        assert_eq!(holds(r"./tests/dangling_else.txt"), vec![false, false, false, false, true]);
+        assert_eq!(holds(r"./tests/lookahead_context.txt"), vec![true, true, true, true, false]);
+        // ELR(1) and free of pilot multiplicities, but S -> S a | B
+        let net = load_net(r"./tests/linear_recursion.txt").unwrap();
+        let ell = &classify(&net)[1];
+        assert!(!ell.holds);
+        assert_eq!(ell.evidence, vec!["machine S is left-recursive through arc 0S -S-> 1S"]);
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::HashSet;
+use crate::analysis::NetAnalysis;
+use crate::ell::*;
+use crate::elr_pilot::*;
+
+fn axiom_and_end(axiom: char, end_marker: char) -> String {
+    let mut res = String::new();
+    res.push_str("/// Machine the whole input must be a sentence of.\n");
+    res.push_str(&format!("pub const AXIOM: char = {:?};\n", axiom));
+    res.push_str("/// Terminal standing for the end of input.\n");
+    res.push_str(&format!("pub const END: char = {:?};\n", end_marker));
+    res
+}
+
+/// Types shared by every generated module.
+const COMMON_RUNTIME: &str = r#"
+/// A token of the input, classified by the terminal it stands for.
+pub trait Token {
+    fn terminal(&self) -> char;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tree<T> {
+    Leaf(T),
+    Node(char, Vec<Tree<T>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    /// The offending terminal, `END` for the end of input.
+    pub found: char,
+    pub expected: Vec<char>,
+}
+"#;
+
+/// Runtime of the generated ELR module, independent of the net: it only
+/// reads the tables emitted before it.
+const ELR_RUNTIME: &str = r#"
+fn transition(state: usize, symbol: char) -> Option<&'static (usize, char, usize, usize, usize)> {
+    TRANSITIONS
+        .binary_search_by_key(&(state, symbol), |t| (t.0, t.1))
+        .ok()
+        .map(|i| &TRANSITIONS[i])
+}
+
+fn reduction(state: usize, lookahead: char) -> Option<&'static (usize, char, usize, char)> {
+    REDUCTIONS
+        .binary_search_by_key(&(state, lookahead), |r| (r.0, r.1))
+        .ok()
+        .map(|i| &REDUCTIONS[i])
+}
+
+fn expected(state: usize) -> Vec<char> {
+    let shifts = TRANSITIONS.iter().filter(|t| t.0 == state && !t.1.is_ascii_uppercase()).map(|t| t.1);
+    let reductions = REDUCTIONS.iter().filter(|r| r.0 == state).map(|r| r.1);
+    let mut res: Vec<char> = shifts.chain(reductions).collect();
+    res.sort();
+    res.dedup();
+    res
+}
+
+struct Element<T> {
+    state: usize,
+    /// For every candidate of `state`, the candidate of the element below
+    /// it was shifted from, or `None` where its machine was entered.
+    pointers: Vec<Option<usize>>,
+    tree: Option<Tree<T>>,
+}
+
+fn push<T>(stack: &mut Vec<Element<T>>, symbol: char, tree: Tree<T>) {
+    let top = stack.last().unwrap().state;
+    let &(_, _, dest, begin, end) = transition(top, symbol).expect("missing goto");
+    let mut pointers = vec![None; CANDIDATES[dest]];
+    for &(src, cand) in &CANDIDATE_MAP[begin..end] {
+        pointers[cand].get_or_insert(src);
+    }
+    stack.push(Element { state: dest, pointers, tree: Some(tree) });
+}
+
+fn origin<T>(stack: &[Element<T>], idx: usize) -> (usize, usize) {
+    let mut k = stack.len() - 1;
+    let mut idx = idx;
+    while let Some(prev) = stack[k].pointers[idx] {
+        idx = prev;
+        k -= 1;
+    }
+    (k, idx)
+}
+
+/// Parses the tokens into a tree whose inner nodes are named after the
+/// machines of the net.
+pub fn parse<T: Token, I: IntoIterator<Item = T>>(tokens: I) -> Result<Tree<T>, ParseError> {
+    let mut tokens = tokens.into_iter();
+    let mut stack = vec![Element { state: 0, pointers: vec![None; CANDIDATES[0]], tree: None }];
+    let mut next = tokens.next();
+    let mut position = 0;
+    loop {
+        let state = stack.last().unwrap().state;
+        let lookahead = next.as_ref().map_or(END, |t| t.terminal());
+        if lookahead != END && !lookahead.is_ascii_uppercase() && transition(state, lookahead).is_some() {
+            let token = next.take().unwrap();
+            push(&mut stack, lookahead, Tree::Leaf(token));
+            next = tokens.next();
+            position += 1;
+            continue;
+        }
+        let Some(&(_, _, idx, machine)) = reduction(state, lookahead) else {
+            return Err(ParseError { position, found: lookahead, expected: expected(state) });
+        };
+        let (k, entry) = origin(&stack, idx);
+        let children = stack.drain(k + 1..).filter_map(|e| e.tree).collect();
+        if lookahead == END && machine == AXIOM && k == 0 && entry == 0 {
+            return Ok(Tree::Node(AXIOM, children));
+        }
+        push(&mut stack, machine, Tree::Node(machine, children));
+    }
+}
+"#;
+
+/// Emits a standalone Rust module parsing the language of the net with
+/// `pilot`: its transitions, candidate maps and reductions as sorted
+/// tables, and a runtime that does not depend on this crate. Pilots with
+/// conflicts have no deterministic tables, so `None` is returned.
+pub fn elr_rust_module(pilot: &Pilot, source: &str) -> Option<String> {
+    let has_conflicts = pilot.states.iter().any(|s| {
+        !s.shift_reduce_conflicts().is_empty()
+            || !s.reduce_reduce_conflicts().is_empty()
+            || !s.convergence_conflicts().is_empty()
+    });
+    if has_conflicts {
+        return None;
+    }
+
+    let candidates: Vec<String> = pilot.states.iter().map(|s| s.candidates.len().to_string()).collect();
+    let mut transitions: Vec<(i32, &PilotTransition)> = pilot.states.iter().flat_map(|s| {
+        s.transitions.iter().map(move |t| (s.id, t))
+    }).collect();
+    transitions.sort_by_key(|(src, t)| (*src, t.character));
+    let mut map_entries: Vec<String> = Vec::new();
+    let transitions: Vec<String> = transitions.into_iter().map(|(src, t)| {
+        let begin = map_entries.len();
+        map_entries.extend(t.candidate_map.iter().map(|(i, j)| format!("({}, {})", i, j)));
+        format!("    ({}, {:?}, {}, {}, {}),", src, t.character, t.dest_id, begin, map_entries.len())
+    }).collect();
+    let mut reductions: Vec<(i32, char, usize, char)> = pilot.states.iter().flat_map(|s| {
+        s.candidates.iter().enumerate().filter(|(i, _)| s.reduces(*i)).map(move |(i, c)| (s.id, c.lookahead, i, c.machine))
+    }).collect();
+    reductions.sort();
+    let reductions: Vec<String> = reductions.into_iter().map(|(s, la, i, m)| {
+        format!("    ({}, {:?}, {}, {:?}),", s, la, i, m)
+    }).collect();
+
+    let mut res = String::new();
+    res.push_str(&format!("// Generated by flc_toolkit from {}. Do not edit.\n", source));
+    res.push_str("#![allow(dead_code)]\n\n");
+    res.push_str(&axiom_and_end(pilot.axiom, pilot.end_marker));
+    res.push_str("/// Number of candidates of each pilot state.\n");
+    res.push_str(&format!("const CANDIDATES: [usize; {}] = [{}];\n\n", candidates.len(), candidates.join(", ")));
+    res.push_str("/// Pilot transitions sorted by source state and symbol: source, symbol,\n");
+    res.push_str("/// destination and the range of their entries in `CANDIDATE_MAP`.\n");
+    res.push_str(&format!("const TRANSITIONS: [(usize, char, usize, usize, usize); {}] = [\n{}\n];\n\n", transitions.len(), transitions.join("\n")));
+    res.push_str("/// Pairs of source and destination candidates shifted by each transition.\n");
+    res.push_str(&format!("const CANDIDATE_MAP: [(usize, usize); {}] = [{}];\n\n", map_entries.len(), map_entries.join(", ")));
+    res.push_str("/// Reductions sorted by state and lookahead: state, lookahead, final\n");
+    res.push_str("/// candidate and its machine.\n");
+    res.push_str(&format!("const REDUCTIONS: [(usize, char, usize, char); {}] = [\n{}\n];\n", reductions.len(), reductions.join("\n")));
+    res.push_str(COMMON_RUNTIME);
+    res.push_str(ELR_RUNTIME);
+    Some(res)
+}
+
+/// Runtime of the generated recursive-descent module: a cursor over the
+/// tokens that the machine functions advance.
+const ELL_RUNTIME: &str = r#"
+struct Parser<T, I> {
+    tokens: I,
+    next: Option<T>,
+    position: usize,
+}
+
+impl<T: Token, I: Iterator<Item = T>> Parser<T, I> {
+    fn lookahead(&self) -> char {
+        self.next.as_ref().map_or(END, |t| t.terminal())
+    }
+
+    fn shift(&mut self) -> Tree<T> {
+        let token = std::mem::replace(&mut self.next, self.tokens.next());
+        self.position += 1;
+        Tree::Leaf(token.unwrap())
+    }
+
+    fn error(&self, expected: &[char]) -> ParseError {
+        ParseError { position: self.position, found: self.lookahead(), expected: expected.to_vec() }
+    }
+"#;
+
+fn char_patterns(set: &[char]) -> String {
+    set.iter().map(|c| format!("{:?}", c)).collect::<Vec<_>>().join(" | ")
+}
+
+fn machine_function(analysis: &NetAnalysis, machine: &Machine) -> String {
+    let mut res: Vec<String> = Vec::new();
+    res.push(format!("    /// Machine {}.", machine.name));
+    res.push(format!("    fn machine_{}(&mut self) -> Result<Tree<T>, ParseError> {{", machine.name.to_ascii_lowercase()));
+    res.push("        let mut children = Vec::new();".to_string());
+    res.push(format!("        let mut state = {};", machine.initial_id()));
+    res.push("        loop {".to_string());
+    res.push("            state = match state {".to_string());
+    let mut states: Vec<&State> = machine.states.iter().collect();
+    states.sort_by_key(|s| s.id);
+    for s in states {
+        res.push(format!("                {} => match self.lookahead() {{", s.id));
+        let mut taken: HashSet<char> = HashSet::new();
+        for (way, guide) in guide_sets(analysis, machine.name, s) {
+            let mut guide: Vec<char> = guide.into_iter().collect();
+            guide.sort();
+            let overlap: Vec<char> = guide.iter().copied().filter(|c| taken.contains(c)).collect();
+            if !overlap.is_empty() {
+                let name = way.map_or("the exit".to_string(), |t| format!("arc -{}->", t.character));
+                res.push(format!("                    // guide-set conflict: {} loses {} to an earlier way", name, char_patterns(&overlap)));
+            }
+            let guide: Vec<char> = guide.into_iter().filter(|c| taken.insert(*c)).collect();
+            if guide.is_empty() {
+                continue;
+            }
+            let action = match way {
+                None => format!("return Ok(Tree::Node({:?}, children))", machine.name),
+                Some(t) if t.is_nonterminal() => {
+                    format!("{{ children.push(self.machine_{}()?); {} }}", t.character.to_ascii_lowercase(), t.dest_id)
+                }
+                Some(t) => format!("{{ children.push(self.shift()); {} }}", t.dest_id)
+            };
+            res.push(format!("                    {} => {},", char_patterns(&guide), action));
+        }
+        let mut expected: Vec<char> = taken.into_iter().collect();
+        expected.sort();
+        res.push(format!("                    _ => return Err(self.error(&[{}])),", expected.iter().map(|c| format!("{:?}", c)).collect::<Vec<_>>().join(", ")));
+        res.push("                },".to_string());
+    }
+    res.push("                _ => unreachable!(),".to_string());
+    res.push("            };".to_string());
+    res.push("        }".to_string());
+    res.push("    }".to_string());
+    res.join("\n")
+}
+
+/// Emits a standalone recursive-descent parser for the net, one function
+/// per machine, every state choosing its way out by the guide set the
+/// lookahead falls in. Nets that are not ELL(1) have guide sets that
+/// overlap, so `None` is returned.
+pub fn ell_rust_module(net: &MachineNet, analysis: &NetAnalysis, source: &str) -> Option<String> {
+    if !guide_conflicts(net, analysis).is_empty() {
+        return None;
+    }
+
+    let mut res = String::new();
+    res.push_str(&format!("// Generated by flc_toolkit from {}. Do not edit.\n", source));
+    res.push_str("#![allow(dead_code, unreachable_code, unused_assignments, unused_mut)]\n\n");
+    res.push_str(&axiom_and_end(net.axiom, net.end_marker));
+    res.push_str(COMMON_RUNTIME);
+    res.push_str(ELL_RUNTIME);
+    for m in &net.machines {
+        res.push('\n');
+        res.push_str(&machine_function(analysis, m));
+        res.push('\n');
+    }
+    res.push_str("}\n\n");
+    res.push_str("/// Parses the tokens into a tree whose inner nodes are named after the\n");
+    res.push_str("/// machines of the net.\n");
+    res.push_str("pub fn parse<T: Token, I: IntoIterator<Item = T>>(tokens: I) -> Result<Tree<T>, ParseError> {\n");
+    res.push_str("    let mut tokens = tokens.into_iter();\n");
+    res.push_str("    let next = tokens.next();\n");
+    res.push_str("    let mut parser = Parser { tokens, next, position: 0 };\n");
+    res.push_str(&format!("    let tree = parser.machine_{}()?;\n", net.axiom.to_ascii_lowercase()));
+    res.push_str("    if parser.lookahead() != END {\n");
+    res.push_str("        return Err(parser.error(&[END]));\n");
+    res.push_str("    }\n");
+    res.push_str("    Ok(tree)\n");
+    res.push_str("}\n");
+    Some(res)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::process::Command;
+    use crate::load_net;
+    use super::*;
+
+    const DRIVER: &str = r#"
+struct Char(char);
+
+impl Token for Char {
+    fn terminal(&self) -> char {
+        self.0
+    }
+}
+
+fn show(tree: &Tree<Char>) -> String {
+    match tree {
+        Tree::Leaf(c) => c.0.to_string(),
+        Tree::Node(m, children) => {
+            let children: Vec<_> = children.iter().map(show).collect();
+            format!("{}({})", m, children.join(" "))
+        }
+    }
+}
+
+fn main() {
+    for arg in std::env::args().skip(1) {
+        match parse(arg.chars().map(Char)) {
+            Ok(tree) => println!("{}", show(&tree)),
+            Err(e) => println!("error at {}: {:?}", e.position, e.expected),
+        }
+    }
+}
+"#;
+
+    /// Compiles the module with a driver printing the tree of each
+    /// argument, runs it and returns its output.
+    fn compile_and_run(module: &str, name: &str, args: &[&str]) -> String {
+        let dir = std::env::temp_dir().join(format!("flc_codegen_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("main.rs");
+        fs::write(&src, format!("{}{}", module, DRIVER)).unwrap();
+        let exe = dir.join("parser");
+        let status = Command::new("rustc").arg("--edition=2021").arg("-o").arg(&exe).arg(&src).status().unwrap();
+        assert!(status.success());
+        let out = Command::new(&exe).args(args).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_generated_module_parses() {
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let module = elr_rust_module(&pilot, "elr_mnet_2024-06-13.txt").unwrap();
+        let out = compile_and_run(&module, "elr", &["dcaabb", "dcab"]);
+        assert_eq!(out, "S(d B(c A(a a B(b)) b))\nerror at 4: ['b']\n");
+    }
+
+    #[test]
+    fn test_generated_ell_module_parses() {
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        let module = ell_rust_module(&net, &NetAnalysis::new(&net), "elr_mnet_2024-06-13.txt").unwrap();
+        let out = compile_and_run(&module, "ell", &["dcaabb", "dcab", ""]);
+        assert_eq!(out, "S(d B(c A(a a B(b)) b))\nerror at 4: ['b']\nS()\n");
+    }
+
+    #[test]
+    fn test_conflicts_refused() {
+        let net = load_net(r"./tests/dangling_else.txt").unwrap();
+        assert!(elr_rust_module(&create_pilot(&net), "dangling_else.txt").is_none());
+        let net = load_net(r"./tests/elr_mnet_book-4.16.txt").unwrap();
+        assert!(ell_rust_module(&net, &NetAnalysis::new(&net), "elr_mnet_book-4.16.txt").is_none());
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use crate::elr_parser::*;
+use crate::elr_pilot::*;
+use crate::sentences::*;
+
+/// Part of a pilot that a parser run can exercise.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub enum CoverageItem {
+    State(i32),
+    Transition(i32, char),
+    Reduction(i32, usize)
+}
+
+impl CoverageItem {
+    pub fn describe(self, pilot: &Pilot, net: &MachineNet) -> String {
+        match self {
+            CoverageItem::State(s) => format!("state I{}", s),
+            CoverageItem::Transition(s, c) => {
+                let dest = pilot.lookup_state(s).transitions.iter().find(|t| t.character == c).unwrap().dest_id;
+                format!("transition I{} -{}-> I{}", s, c, dest)
+            }
+            CoverageItem::Reduction(s, i) => {
+                format!("reduction of {} in I{}", pilot.lookup_state(s).candidates[i].describe(net), s)
+            }
+        }
+    }
+}
+
+pub struct CoverageReport {
+    pub sentences: Vec<String>,
+    pub uncovered: Vec<CoverageItem>
+}
+
+/// Number of shortest sentences and of random samples tried as candidates.
+const SHORTEST_POOL: usize = 500;
+const SAMPLE_POOL: usize = 200;
+
+/// Accepting runs considered per sentence when the pilot has conflicts.
+const MAX_RUNS: usize = 8;
+
+/// Every state, transition and final-candidate reduction of the pilot.
+pub fn coverage_items(pilot: &Pilot) -> HashSet<CoverageItem> {
+    let mut res: HashSet<CoverageItem> = HashSet::new();
+    for s in &pilot.states {
+        res.insert(CoverageItem::State(s.id));
+        for t in &s.transitions {
+            res.insert(CoverageItem::Transition(s.id, t.character));
+        }
+        for i in 0..s.candidates.len() {
+            if s.reduces(i) {
+                res.insert(CoverageItem::Reduction(s.id, i));
+            }
+        }
+    }
+    res
+}
+
+fn covered_by(pilot: &Pilot, sentence: &str) -> HashSet<CoverageItem> {
+    let max_idle = 2 * pilot.states.len();
+    let runs = find_accepting_runs(pilot, sentence, max_idle, MAX_RUNS);
+    if runs.is_empty() {
+        return HashSet::new();
+    }
+    let mut res = HashSet::from([CoverageItem::State(0)]);
+    for e in runs.into_iter().flat_map(|(_, events)| events) {
+        match e {
+            Event::Transition(s, c) => {
+                let dest = pilot.lookup_state(s).transitions.iter().find(|t| t.character == c).unwrap().dest_id;
+                res.insert(CoverageItem::Transition(s, c));
+                res.insert(CoverageItem::State(dest));
+            }
+            Event::Reduction(s, i) => {
+                res.insert(CoverageItem::Reduction(s, i));
+            }
+        }
+    }
+    res
+}
+
+/// Picks a small set of sentences of length up to `max_len` that together
+/// exercise as much of the pilot as possible, greedily taking the sentence
+/// that covers the most items still uncovered. Candidates are the shortest
+/// sentences of the net plus seeded random samples.
+pub fn coverage_sentences(pilot: &Pilot, net: &MachineNet, max_len: usize) -> CoverageReport {
+    let mut pool = shortest_sentences(net, SHORTEST_POOL, max_len);
+    for seed in 0..SAMPLE_POOL {
+        let mut sampler = SentenceSampler::new(net, seed as u64, 1 + seed % max_len.max(1));
+        if let Some(s) = sampler.sample() {
+            if s.len() <= max_len && !pool.contains(&s) {
+                pool.push(s);
+            }
+        }
+    }
+    let mut candidates: Vec<(String, HashSet<CoverageItem>)> = pool.into_iter().map(|s| {
+        let covered = covered_by(pilot, &s);
+        (s, covered)
+    }).collect();
+
+    let mut uncovered = coverage_items(pilot);
+    let mut sentences: Vec<String> = Vec::new();
+    loop {
+        let best = candidates.iter().enumerate().max_by_key(|(i, (_, cov))| {
+            // prefer earlier (shorter) candidates on ties
+            (cov.intersection(&uncovered).count(), usize::MAX - i)
+        });
+        let Some((i, (_, cov))) = best else {
+            break;
+        };
+        if cov.intersection(&uncovered).count() == 0 {
+            break;
+        }
+        let (s, cov) = candidates.remove(i);
+        uncovered.retain(|item| !cov.contains(item));
+        sentences.push(s);
+    }
+    let mut uncovered: Vec<_> = uncovered.into_iter().collect();
+    uncovered.sort();
+    CoverageReport{sentences, uncovered}
+}
+
+impl CoverageReport {
+    pub fn print(&self, pilot: &Pilot, net: &MachineNet) {
+        for s in &self.sentences {
+            println!("{}", if s.is_empty() { "ε" } else { s });
+        }
+        for item in &self.uncovered {
+            eprintln!("not covered: {}", item.describe(pilot, net));
+        }
+        if self.uncovered.is_empty() {
+            eprintln!("all states, transitions and reductions covered");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    fn uncovered(net: &MachineNet, max_len: usize) -> (Vec<String>, Vec<String>) {
+        let pilot = create_pilot(net);
+        let report = coverage_sentences(&pilot, net, max_len);
+        (report.sentences, report.uncovered.iter().map(|i| i.describe(&pilot, net)).collect())
+    }
+
+    #[test]
+    fn test_coverage_sentences() {
+        let net = load_net(r"./tests/dangling_else.txt").unwrap();
+        assert_eq!(coverage_items(&create_pilot(&net)).len(), 51);
+        assert_eq!(uncovered(&net, 8), (vec!["aacbacbc".to_string(), "aaac".to_string()], vec![]));
+        // Reducing the inner B on b needs a nested if-then-else
+        let (sentences, items) = uncovered(&net, 6);
+        assert_eq!(sentences, vec!["aacbac", "aaacbc"]);
+        assert_eq!(items, vec!["reduction of <(2B), b> in I14"]);
+    }
+
+    #[test]
+    fn test_uncovered_items() {
+        let net = load_net(r"./tests/named_states.txt").unwrap();
+        let (sentences, items) = uncovered(&net, 6);
+        assert_eq!(sentences, vec!["aaabbb", "aaaabb", "aab", "ab", "aabb"]);
+        assert_eq!(items, vec![
+            "state I13", "state I15",
+            "transition I8 -A-> I6", "transition I12 -A-> I6", "transition I12 -B-> I13",
+            "transition I12 -a-> I8", "transition I13 -b-> I15",
+            "reduction of <(9A), b> in I14", "reduction of <(E5B), b> in I15"
+        ]);
+    }
+}
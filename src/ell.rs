@@ -0,0 +1,247 @@
+use std::collections::HashSet;
+use crate::analysis::NetAnalysis;
+use crate::analysis::set_to_string;
+use crate::elr_pilot::*;
+
+/// Terminals that select an arc of a machine state when parsing top-down:
+/// the arc label, or the initials of the called machine completed by what
+/// follows the arc when the machine is nullable.
+pub fn arc_guide_set(analysis: &NetAnalysis, machine: char, t: &Transition) -> HashSet<char> {
+    if !t.is_nonterminal() {
+        return HashSet::from([t.character]);
+    }
+    let mut res = analysis.initials(t.character, analysis.initial_ids[&t.character]).clone();
+    if analysis.is_nullable(t.character) {
+        res.extend(analysis.initials(machine, t.dest_id));
+        if analysis.is_state_nullable(machine, t.dest_id) {
+            res.extend(analysis.follow(machine));
+        }
+    }
+    res
+}
+
+/// Terminals that select leaving the machine from a final state.
+pub fn exit_guide_set(analysis: &NetAnalysis, machine: char) -> HashSet<char> {
+    analysis.follow(machine).clone()
+}
+
+/// The ways out of a machine state with their guide sets, arcs in the
+/// order they were declared and the exit (`None`) last.
+pub fn guide_sets<'a>(analysis: &NetAnalysis, machine: char, state: &'a State) -> Vec<(Option<&'a Transition>, HashSet<char>)> {
+    let mut res: Vec<(Option<&Transition>, HashSet<char>)> = state.transitions.iter().map(|t| {
+        (Some(t), arc_guide_set(analysis, machine, t))
+    }).collect();
+    if state.is_final {
+        res.push((None, exit_guide_set(analysis, machine)));
+    }
+    res
+}
+
+/// Two ways out of the same state whose guide sets intersect. `None` is
+/// the exit from a final state.
+pub struct GuideConflict {
+    pub machine: char,
+    pub state: i32,
+    pub first: Option<char>,
+    pub second: Option<char>,
+    pub common: HashSet<char>
+}
+
+fn describe_way(c: &GuideConflict, way: Option<char>, net: &MachineNet) -> String {
+    match way {
+        Some(ch) => {
+            let dest = net.lookup_state(c.machine, c.state).try_lookup_transition(ch).unwrap().dest_id;
+            format!("arc {} -{}-> {}", net.state_label(c.machine, c.state), ch, net.state_label(c.machine, dest))
+        }
+        None => format!("exit from {}", net.state_label(c.machine, c.state))
+    }
+}
+
+impl GuideConflict {
+    pub fn print(&self, net: &MachineNet) {
+        let first = describe_way(self, self.first, net);
+        let second = describe_way(self, self.second, net);
+        eprintln!("state {}: guide sets of {} and {} share {}", net.state_label(self.machine, self.state), first, second, set_to_string(&self.common));
+        let state = net.lookup_state(self.machine, self.state);
+        for way in [self.first, self.second].into_iter().flatten() {
+            let t = state.try_lookup_transition(way).unwrap();
+            t.location.emit_note(&format!("arc -{}-> defined here", way));
+        }
+    }
+}
+
+/// Every pair of intersecting guide sets; the net is ELL(1) when there is
+/// none.
+pub fn guide_conflicts(net: &MachineNet, analysis: &NetAnalysis) -> Vec<GuideConflict> {
+    let mut res: Vec<GuideConflict> = Vec::new();
+    for m in &net.machines {
+        for s in &m.states {
+            let ways = guide_sets(analysis, m.name, s);
+            for (i, (first, set_1)) in ways.iter().enumerate() {
+                for (second, set_2) in &ways[i+1..] {
+                    let common: HashSet<char> = set_1.intersection(set_2).copied().collect();
+                    if !common.is_empty() {
+                        res.push(GuideConflict{
+                            machine: m.name,
+                            state: s.id,
+                            first: first.map(|t| t.character),
+                            second: second.map(|t| t.character),
+                            common
+                        });
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+/// What the top-down parser did in one step.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EllMove {
+    Shift(char, i32),
+    /// Call of a machine, with the state to return to.
+    Call(char, i32),
+    Return,
+    Accept,
+    /// No guide set contains the lookahead; the terminals that would do.
+    Error(Vec<char>)
+}
+
+#[derive(Debug, Clone)]
+pub struct EllStep {
+    pub machine: char,
+    pub state: i32,
+    /// Return states, the innermost last.
+    pub stack: Vec<(char, i32)>,
+    pub lookahead: char,
+    pub chosen: EllMove,
+    /// The other ways out whose guide set also contains the lookahead,
+    /// present when the choice was nondeterministic.
+    pub also_possible: Vec<EllMove>
+}
+
+fn way_move(way: Option<&Transition>) -> EllMove {
+    match way {
+        Some(t) if t.is_nonterminal() => EllMove::Call(t.character, t.dest_id),
+        Some(t) => EllMove::Shift(t.character, t.dest_id),
+        None => EllMove::Return
+    }
+}
+
+fn display_move(m: &EllMove, net: &MachineNet, machine: char, state: i32) -> String {
+    match m {
+        EllMove::Shift(c, dest) => format!("arc {} -{}-> {}", net.state_label(machine, state), c, net.state_label(machine, *dest)),
+        EllMove::Call(c, dest) => format!("call {} by arc {} -{}-> {}", c, net.state_label(machine, state), c, net.state_label(machine, *dest)),
+        EllMove::Return => format!("return from {}", machine),
+        EllMove::Accept => "accept".to_string(),
+        EllMove::Error(expected) => {
+            let expected: Vec<_> = expected.iter().map(|c| if *c == net.end_marker { "⊣".to_string() } else { c.to_string() }).collect();
+            format!("error, expected one of {}", expected.join(" "))
+        }
+    }
+}
+
+impl EllStep {
+    pub fn print(&self, net: &MachineNet) {
+        let stack: Vec<_> = self.stack.iter().map(|(m, s)| net.state_label(*m, *s)).collect();
+        let lookahead = if self.lookahead == net.end_marker { "⊣".to_string() } else { self.lookahead.to_string() };
+        println!("{}  [{}]  {}  {}", net.state_label(self.machine, self.state), stack.join(" "), lookahead, display_move(&self.chosen, net, self.machine, self.state));
+        if !self.also_possible.is_empty() {
+            let others: Vec<_> = self.also_possible.iter().map(|m| display_move(m, net, self.machine, self.state)).collect();
+            println!("  guide-set conflict: {} also possible", others.join(", "));
+        }
+    }
+}
+
+/// Parses `input` top-down, choosing at each state the first way out whose
+/// guide set holds the lookahead and keeping the return states on an
+/// explicit stack. Stops at the first error, or when the stack grows
+/// deeper than the input could justify, as left recursion makes it.
+pub fn ell_simulate(net: &MachineNet, analysis: &NetAnalysis, input: &str) -> Vec<EllStep> {
+    let input: Vec<char> = input.chars().collect();
+    let max_depth = input.len() + net.machines.iter().map(|m| m.states.len()).sum::<usize>();
+    let mut steps: Vec<EllStep> = Vec::new();
+    let mut stack: Vec<(char, i32)> = Vec::new();
+    let (mut machine, mut state) = (net.axiom, net.initial_id(net.axiom));
+    let mut pos = 0;
+    loop {
+        let lookahead = input.get(pos).copied().unwrap_or(net.end_marker);
+        let ways = guide_sets(analysis, machine, net.lookup_state(machine, state));
+        let mut fitting = ways.iter().filter(|(_, g)| g.contains(&lookahead)).map(|(w, _)| way_move(*w));
+        let mut step = EllStep{machine, state, stack: stack.clone(), lookahead, chosen: EllMove::Return, also_possible: vec![]};
+        let Some(chosen) = fitting.next() else {
+            let mut expected: Vec<char> = ways.into_iter().flat_map(|(_, g)| g).collect();
+            expected.sort();
+            expected.dedup();
+            step.chosen = EllMove::Error(expected);
+            steps.push(step);
+            return steps;
+        };
+        step.also_possible = fitting.collect();
+        match chosen {
+            EllMove::Shift(_, dest) => {
+                pos += 1;
+                state = dest;
+            }
+            EllMove::Call(callee, dest) => {
+                stack.push((machine, dest));
+                (machine, state) = (callee, net.initial_id(callee));
+            }
+            _ => match stack.pop() {
+                Some((caller, dest)) => (machine, state) = (caller, dest),
+                None => {
+                    // Leaving the axiom is only right at the end of input
+                    step.chosen = if lookahead == net.end_marker { EllMove::Accept } else { EllMove::Error(vec![net.end_marker]) };
+                    steps.push(step);
+                    return steps;
+                }
+            }
+        }
+        step.chosen = chosen;
+        steps.push(step);
+        if stack.len() > max_depth {
+            eprintln!("error: the return stack grows without reading input, the net is left-recursive");
+            return steps;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    #[test]
+    fn test_guide_conflicts() {
+        let net = load_net(r"./tests/elr_mnet_book-4.16.txt").unwrap();
+        let analysis = NetAnalysis::new(&net);
+        let conflicts = guide_conflicts(&net, &analysis);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].machine, conflicts[0].state), ('S', 0));
+        assert_eq!(conflicts[0].common, HashSet::from(['a']));
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        assert!(guide_conflicts(&net, &NetAnalysis::new(&net)).is_empty());
+    }
+
+    #[test]
+    fn test_ell_simulate() {
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        let analysis = NetAnalysis::new(&net);
+        let steps = ell_simulate(&net, &analysis, "dcabb");
+        assert_eq!(steps.last().unwrap().chosen, EllMove::Accept);
+        assert!(steps.iter().all(|s| s.also_possible.is_empty()));
+        let call = steps.iter().find(|s| s.chosen == EllMove::Call('A', 2)).unwrap();
+        assert_eq!(call.stack, vec![('S', 2)]);
+        let steps = ell_simulate(&net, &analysis, "dcx");
+        assert_eq!(steps.last().unwrap().chosen, EllMove::Error(vec!['a']));
+
+        let net = load_net(r"./tests/elr_mnet_book-4.16.txt").unwrap();
+        let steps = ell_simulate(&net, &NetAnalysis::new(&net), "abd");
+        assert_eq!(steps[0].also_possible, vec![EllMove::Call('A', 5)]);
+        assert_eq!(steps.last().unwrap().chosen, EllMove::Accept);
+        // Follow(S) holds e, but an e left over at the end is not accepted
+        let steps = ell_simulate(&net, &NetAnalysis::new(&net), "bce");
+        assert_eq!(steps.last().unwrap().chosen, EllMove::Error(vec!['$']));
+    }
+}
@@ -0,0 +1,193 @@
+pub mod recovery;
+
+use crate::elr_pilot::*;
+use crate::syntax_tree::SyntaxTree;
+pub use recovery::*;
+
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum Action {
+    Shift(char),
+    Reduce(usize),
+    Accept(usize)
+}
+
+/// Something the parser did to the pilot, used to trace and measure runs.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum Event {
+    Transition(i32, char),
+    Reduction(i32, usize)
+}
+
+#[derive(Debug, Clone)]
+struct StackElement {
+    state: i32,
+    /// For every candidate of `state`, the index of the candidate in the
+    /// element below it was shifted from, or `None` for candidates
+    /// introduced by the closure.
+    pointers: Vec<Option<usize>>,
+    tree: Option<SyntaxTree>
+}
+
+/// Configuration of an ELR(1) parser driven by a pilot: a stack of pilot
+/// states whose candidates point back to where their machine was entered,
+/// so that reductions know how many elements to pop.
+#[derive(Debug, Clone)]
+pub struct Configuration<'a> {
+    pilot: &'a Pilot,
+    stack: Vec<StackElement>
+}
+
+impl<'a> Configuration<'a> {
+    pub fn new(pilot: &'a Pilot) -> Configuration<'a> {
+        let n = pilot.lookup_state(0).candidates.len();
+        let bottom = StackElement{state: 0, pointers: vec![None; n], tree: None};
+        Configuration{pilot, stack: vec![bottom]}
+    }
+
+    pub fn top_state(&self) -> &'a PilotState {
+        self.pilot.lookup_state(self.stack.last().unwrap().state)
+    }
+
+    /// Follows the pointers of candidate `idx` of the top element back to
+    /// the element where its machine was entered.
+    fn origin(&self, idx: usize) -> (usize, usize) {
+        let mut k = self.stack.len() - 1;
+        let mut idx = idx;
+        while let Some(prev) = self.stack[k].pointers[idx] {
+            idx = prev;
+            k -= 1;
+        }
+        (k, idx)
+    }
+
+    /// Every action allowed with `lookahead` (the end marker at the end of
+    /// input),
+    /// shifts first. More than one action means a conflict.
+    pub fn actions(&self, lookahead: char) -> Vec<Action> {
+        let state = self.top_state();
+        let mut res: Vec<Action> = Vec::new();
+        if state.transitions.iter().any(|t| t.character == lookahead) {
+            res.push(Action::Shift(lookahead));
+        }
+        for (i, c) in state.candidates.iter().enumerate() {
+            if state.reduces(i) && c.lookahead == lookahead {
+                if lookahead == self.pilot.end_marker && c.machine == self.pilot.axiom && self.origin(i) == (0, 0) {
+                    res.push(Action::Accept(i));
+                } else {
+                    res.push(Action::Reduce(i));
+                }
+            }
+        }
+        res
+    }
+
+    /// Terminals allowed in the current state: the labels of the outgoing
+    /// terminal edges and the lookaheads of the reducible candidates, the
+    /// end marker standing for the end of input.
+    pub fn expected(&self) -> Vec<char> {
+        let state = self.top_state();
+        let mut res: Vec<char> = state.transitions.iter().filter(|t| !t.is_nonterminal()).map(|t| t.character).collect();
+        for (i, c) in state.candidates.iter().enumerate() {
+            if state.reduces(i) {
+                res.push(c.lookahead);
+            }
+        }
+        res.sort();
+        res.dedup();
+        res
+    }
+
+    fn push(&mut self, character: char, tree: SyntaxTree) -> Event {
+        let top = self.stack.last().unwrap().state;
+        let trans = self.pilot.lookup_state(top).transitions.iter()
+            .find(|t| t.character == character)
+            .unwrap_or_else(|| panic!("no transition I{top} -{character}->"));
+        let n = self.pilot.lookup_state(trans.dest_id).candidates.len();
+        let mut pointers = vec![None; n];
+        for (src, dest) in &trans.candidate_map {
+            pointers[*dest].get_or_insert(*src);
+        }
+        self.stack.push(StackElement{state: trans.dest_id, pointers, tree: Some(tree)});
+        Event::Transition(top, character)
+    }
+
+    /// Performs `action` and returns what happened in the pilot. Accepting
+    /// only reports the final reduction of the axiom.
+    pub fn apply(&mut self, action: Action) -> Vec<Event> {
+        match action {
+            Action::Shift(c) => vec![self.push(c, SyntaxTree::Leaf(c))],
+            Action::Reduce(idx) => {
+                let top = self.stack.last().unwrap().state;
+                let machine = self.top_state().candidates[idx].machine;
+                let (k, _) = self.origin(idx);
+                let children = self.stack.drain(k + 1..).filter_map(|e| e.tree).collect();
+                let goto = self.push(machine, SyntaxTree::Node(machine, children));
+                vec![Event::Reduction(top, idx), goto]
+            }
+            Action::Accept(idx) => vec![Event::Reduction(self.stack.last().unwrap().state, idx)]
+        }
+    }
+
+    /// Syntax tree of the whole input, available once `Accept` is allowed.
+    pub fn accepted_tree(&self) -> SyntaxTree {
+        let children = self.stack[1..].iter().filter_map(|e| e.tree.clone()).collect();
+        SyntaxTree::Node(self.pilot.axiom, children)
+    }
+}
+
+/// Explores every way the pilot can parse `input`, also through conflicts,
+/// and returns the trees and events of up to `limit` accepting runs.
+/// Runs of more than `max_idle` consecutive actions that do not consume
+/// input are abandoned, which bounds loops through empty reductions.
+pub fn find_accepting_runs(pilot: &Pilot, input: &str, max_idle: usize, limit: usize) -> Vec<(SyntaxTree, Vec<Event>)> {
+    let input: Vec<char> = input.chars().collect();
+    let mut res: Vec<(SyntaxTree, Vec<Event>)> = Vec::new();
+    let mut work = vec![(Configuration::new(pilot), 0, 0, Vec::new())];
+    while let Some((config, pos, idle, events)) = work.pop() {
+        let lookahead = input.get(pos).copied().unwrap_or(pilot.end_marker);
+        let mut actions = config.actions(lookahead);
+        // Reversed so that the preferred action is popped first.
+        actions.reverse();
+        for action in actions {
+            let consumes = matches!(action, Action::Shift(_));
+            if !consumes && idle >= max_idle {
+                continue;
+            }
+            let mut next = config.clone();
+            let mut next_events = events.clone();
+            next_events.extend(next.apply(action));
+            if let Action::Accept(_) = action {
+                res.push((next.accepted_tree(), next_events));
+                if res.len() >= limit {
+                    return res;
+                }
+            } else if consumes {
+                work.push((next, pos + 1, 0, next_events));
+            } else {
+                work.push((next, pos, idle + 1, next_events));
+            }
+        }
+    }
+    res
+}
+
+pub fn find_accepting_run(pilot: &Pilot, input: &str, max_idle: usize) -> Option<(SyntaxTree, Vec<Event>)> {
+    find_accepting_runs(pilot, input, max_idle, 1).pop()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    #[test]
+    fn test_find_accepting_run() {
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let (tree, _) = find_accepting_run(&pilot, "dcaabb", 8).unwrap();
+        assert_eq!(tree.to_string(), "S(d B(c A(a a B(b)) b))");
+        assert!(find_accepting_run(&pilot, "dcab", 8).is_none());
+        let (tree, _) = find_accepting_run(&pilot, "", 8).unwrap();
+        assert_eq!(tree.to_string(), "S()");
+    }
+}
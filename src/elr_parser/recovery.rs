@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use crate::analysis::NetAnalysis;
+use crate::elr_parser::*;
+
+/// Terminals at which parsing resumes after an error inside each machine.
+pub type SyncSets = HashMap<char, HashSet<char>>;
+
+/// Synchronizing terminals taken from the follow sets of the machines,
+/// the usual choice for panic-mode recovery.
+pub fn follow_sync_sets(net: &MachineNet) -> SyncSets {
+    NetAnalysis::new(net).follow
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxError {
+    pub position: usize,
+    pub found: char,
+    pub expected: Vec<char>,
+    /// Number of terminals skipped to recover, and the machine whose goto
+    /// was taken; `None` when recovery failed and parsing stopped.
+    pub recovery: Option<(usize, char)>
+}
+
+pub struct RecoveredParse {
+    /// The syntax tree, when the parser reached the end of input. Machines
+    /// completed by recovery hold whatever was on the stack above them.
+    pub tree: Option<SyntaxTree>,
+    pub errors: Vec<SyntaxError>
+}
+
+fn display_terminal(c: char, end_marker: char) -> String {
+    if c == end_marker { "⊣".to_string() } else { format!("'{}'", c) }
+}
+
+impl SyntaxError {
+    pub fn print(&self, end_marker: char) {
+        let expected: Vec<_> = self.expected.iter().map(|c| display_terminal(*c, end_marker)).collect();
+        eprintln!("error at position {}: unexpected {}, expected {}", self.position, display_terminal(self.found, end_marker), expected.join(", "));
+        match self.recovery {
+            Some((skipped, machine)) => eprintln!("note: skipped {} terminals and resumed after {}", skipped, machine),
+            None => eprintln!("note: cannot recover, giving up")
+        }
+    }
+}
+
+impl<'a> Configuration<'a> {
+    /// Panic-mode recovery: finds the stack element and nonterminal edge
+    /// whose synchronizing set contains the nearest upcoming terminal the
+    /// parser can continue with, drops the elements above it and takes the
+    /// nonterminal edge. Returns the position to resume at and the machine.
+    fn recover(&mut self, input: &[char], from: usize, sync: &SyncSets) -> Option<(usize, char)> {
+        for j in from..=input.len() {
+            let t = input.get(j).copied().unwrap_or(self.pilot.end_marker);
+            for k in (0..self.stack.len()).rev() {
+                let state = self.pilot.lookup_state(self.stack[k].state);
+                for trans in state.transitions.iter().filter(|t| t.is_nonterminal()) {
+                    if !sync.get(&trans.character).is_some_and(|s| s.contains(&t)) {
+                        continue;
+                    }
+                    let mut next = self.clone();
+                    let children = next.stack.drain(k + 1..).filter_map(|e| e.tree).collect();
+                    next.push(trans.character, SyntaxTree::Node(trans.character, children));
+                    if !next.actions(t).is_empty() {
+                        *self = next;
+                        return Some((j, trans.character));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parses `input` deterministically, taking the first action when the
+/// pilot has a conflict, and recovers from every error in panic mode so
+/// that one run reports them all.
+pub fn parse_with_recovery(pilot: &Pilot, input: &str, sync: &SyncSets) -> RecoveredParse {
+    let input: Vec<char> = input.chars().collect();
+    let max_idle = 2 * pilot.states.len();
+    let mut config = Configuration::new(pilot);
+    let mut errors: Vec<SyntaxError> = Vec::new();
+    let mut pos = 0;
+    let mut idle = 0;
+    loop {
+        let lookahead = input.get(pos).copied().unwrap_or(pilot.end_marker);
+        let action = config.actions(lookahead).first().copied().filter(|_| idle <= max_idle);
+        match action {
+            Some(Action::Accept(_)) => {
+                return RecoveredParse{tree: Some(config.accepted_tree()), errors};
+            }
+            Some(action) => {
+                config.apply(action);
+                if let Action::Shift(_) = action {
+                    pos += 1;
+                    idle = 0;
+                } else {
+                    idle += 1;
+                }
+            }
+            None => {
+                // Resuming where the last error did could loop forever.
+                let again = errors.last().is_some_and(|e| e.position == pos);
+                let from = if again { pos + 1 } else { pos };
+                let expected = config.expected();
+                let recovery = if from <= input.len() { config.recover(&input, from, sync) } else { None };
+                errors.push(SyntaxError{
+                    position: pos,
+                    found: lookahead,
+                    expected,
+                    recovery: recovery.map(|(j, machine)| (j - pos, machine))
+                });
+                let Some((j, _)) = recovery else {
+                    return RecoveredParse{tree: None, errors};
+                };
+                pos = j;
+                idle = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    #[test]
+    fn test_expected_terminals() {
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let res = parse_with_recovery(&pilot, "dcx", &follow_sync_sets(&net));
+        assert_eq!(res.errors[0].position, 2);
+        assert_eq!(res.errors[0].expected, vec!['a']);
+    }
+
+    #[test]
+    fn test_several_errors() {
+        let net = load_net(r"./tests/expr_prec.txt").unwrap();
+        let mut pilot = create_pilot(&net);
+        pilot.resolve_conflicts(&net);
+        let res = parse_with_recovery(&pilot, "npxnptnpn", &follow_sync_sets(&net));
+        assert_eq!(res.errors.len(), 2);
+        assert!(res.errors.iter().all(|e| e.recovery.is_some()));
+        assert!(res.tree.is_some());
+        let res = parse_with_recovery(&pilot, "npn", &follow_sync_sets(&net));
+        assert!(res.errors.is_empty());
+    }
+}
@@ -1,22 +1,37 @@
+pub mod counterexample;
 pub mod dot_formatter;
+pub mod elr_k;
+pub mod lalr;
+pub mod lr0;
+pub mod precedence;
 
+use std::fmt;
 use std::collections::VecDeque;
 use std::collections::HashSet;
+use std::hash::Hash;
 pub use crate::fsm::*;
+pub use elr_k::create_k_pilot;
+pub use lr0::create_lr0_pilot;
+use crate::analysis::NetAnalysis;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MachineNet {
-    pub machines: Vec<Machine>
+    /// Name of the `mnet` block, needed when a file holds several nets.
+    pub name: Option<String>,
+    pub machines: Vec<Machine>,
+    pub precedence: precedence::Precedence,
+    /// Machine whose language is the language of the net, `S` by default.
+    pub axiom: char,
+    /// Terminal marking the end of input in lookaheads, `$` by default.
+    pub end_marker: char
 }
 
+pub const DEFAULT_AXIOM: char = 'S';
+pub const DEFAULT_END_MARKER: char = '$';
+
 impl MachineNet {
     pub fn try_lookup_machine(&self, machine: char) -> Option<&Machine> {
-        for m in &self.machines {
-            if m.name == machine {
-                return Some(m);
-            }
-        }
-        None
+        self.machines.iter().find(|m| m.name == machine)
     }
 
     pub fn lookup_machine(&self, machine: char) -> &Machine {
@@ -39,8 +54,18 @@ impl MachineNet {
         self.lookup_machine(machine).lookup_state(id)
     }
 
+    pub fn initial_id(&self, machine: char) -> i32 {
+        self.lookup_machine(machine).initial_id()
+    }
+
+    /// The state as written in diagnostics, its name followed by the
+    /// machine name, e.g. `q_startS`.
+    pub fn state_label(&self, machine: char, id: i32) -> String {
+        format!("{}{}", self.lookup_machine(machine).state_name(id), machine)
+    }
+
     fn validate_machine_count(&self) -> bool {
-        if self.machines.len() == 0 {
+        if self.machines.is_empty() {
             eprintln!("error: no machines in the machine net");
             false
         } else {
@@ -48,22 +73,42 @@ impl MachineNet {
         }
     }
 
+    /// The same net with another machine as axiom, to analyze the language
+    /// of one of its entry points.
+    pub fn with_axiom(&self, axiom: char) -> MachineNet {
+        MachineNet{axiom, ..self.clone()}
+    }
+
     fn validate_start(&self) -> bool {
-        // There must be a S-named machine
+        // There must be a machine named as the axiom
         for m in &self.machines {
-            if m.name == 'S' {
+            if m.name == self.axiom {
                 return true;
             }
         }
-        eprintln!("error: axiom (machine named S) missing");
+        eprintln!("error: axiom (machine named {}) missing", self.axiom);
         false
     }
 
+    fn validate_end_marker(&self) -> bool {
+        let c = self.end_marker;
+        if c.is_ascii_uppercase() || c.is_whitespace() || c == NO_LOOKAHEAD {
+            eprintln!("error: {:?} cannot be the end marker", c);
+            return false;
+        }
+        let used = self.machines.iter().flat_map(|m| &m.states).flat_map(|s| &s.transitions).find(|t| t.character == c);
+        if let Some(t) = used {
+            t.location.emit_error(&format!("terminal '{}' is also the end marker", c));
+            return false;
+        }
+        true
+    }
+
     fn validate_state_count(&self) -> bool {
         // All machines must have > 0 states
         let mut res = true;
         for m in &self.machines {
-            if m.states.len() == 0 {
+            if m.states.is_empty() {
                 eprintln!("error: machine {} has zero states", m.name);
                 res = false;
             }
@@ -72,17 +117,17 @@ impl MachineNet {
     }
 
     fn validate_single_initial_state(&self) -> bool {
-        // The initial state must be state 0. All other states are not initial
+        // Exactly one state per machine is initial, whatever its name
         let mut res = true;
         for m in &self.machines {
-            for s in &m.states {
-                if s.is_initial && s.id != 0 {
-                    eprintln!("error: state {}{} cannot be initial", m.name, s.id);
-                    res = false;
-                } else if s.id == 0 && !s.is_initial {
-                    eprintln!("error: state {}{} must be initial", m.name, s.id);
-                    res = false;
-                }
+            let initials: Vec<&State> = m.states.iter().filter(|s| s.is_initial).collect();
+            if initials.is_empty() && !m.states.is_empty() {
+                eprintln!("error: no initial state in machine {}", m.name);
+                res = false;
+            } else if initials.len() > 1 {
+                let names: Vec<_> = initials.iter().map(|s| format!("{}{}", s.name, m.name)).collect();
+                eprintln!("error: machine {} has several initial states: {}", m.name, names.join(", "));
+                res = false;
             }
         }
         res
@@ -104,19 +149,17 @@ impl MachineNet {
         for m in &self.machines {
             for s in &m.states {
                 for (i, t) in s.transitions.iter().enumerate() {
-                    if let None = m.try_lookup_state(t.dest_id) {
-                        eprintln!("error: transition {}{} -{}-> {}{} goes to a non-existent state", m.name, s.id, t.character, m.name, t.dest_id);
+                    if m.try_lookup_state(t.dest_id).is_none() {
+                        eprintln!("error: transition {}{} -{}-> {}{} goes to a non-existent state", s.name, m.name, t.character, t.dest_id, m.name);
                         res = false;
                     }
-                    if t.is_nonterminal() {
-                        if let None = self.try_lookup_machine(t.character) {
-                            eprintln!("error: transition {}{} -{}-> ... has an invalid nonterminal", m.name, s.id, t.character);
-                            res = false;
-                        }
+                    if t.is_nonterminal() && self.try_lookup_machine(t.character).is_none() {
+                        eprintln!("error: transition {}{} -{}-> ... has an invalid nonterminal", s.name, m.name, t.character);
+                        res = false;
                     }
                     for tj in &s.transitions[i+1..] {
                         if t.character == tj.character {
-                            eprintln!("error: multiple transitions {}{} -{}-> ...", m.name, s.id, t.character);
+                            eprintln!("error: multiple transitions {}{} -{}-> ...", s.name, m.name, t.character);
                             res = false;
                         }
                     }
@@ -126,66 +169,134 @@ impl MachineNet {
         res
     }
 
+    fn validate_precedence(&self) -> bool {
+        let mut res = true;
+        for (machine, (t, location)) in &self.precedence.machines {
+            if self.try_lookup_machine(*machine).is_none() {
+                location.emit_error(&format!("%prec given for non-existent machine {}", machine));
+                res = false;
+            } else if self.precedence.terminal(*t).is_none() {
+                location.emit_error(&format!("machine {} takes the precedence of '{}', which has none", machine, t));
+                res = false;
+            }
+        }
+        res
+    }
+
     pub fn validate(&self) -> bool {
         [
             self.validate_machine_count(),
             self.validate_start(),
+            self.validate_end_marker(),
             self.validate_state_count(),
             self.validate_single_initial_state(),
             self.validate_any_final_state(),
-            self.validate_transitions()
+            self.validate_transitions(),
+            self.validate_precedence()
         ].into_iter().all(|v| v)
     }
+}
 
-    fn followers_impl(&self, machine: char, id: i32, visited: &mut HashSet<(char, i32)>, next: &HashSet<char>) -> HashSet<char> {
-        if visited.contains(&(machine, id)) {
-            return HashSet::new();
+/// Prints the net in the syntax of machine net files.
+impl fmt::Display for MachineNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => writeln!(f, "mnet {} {{", name)?,
+            None => writeln!(f, "mnet {{")?
         }
-        visited.insert((machine, id));
-        let state = self.lookup_state(machine, id);
-        let mut res: HashSet<char> = HashSet::new();
-        if state.is_final {
-            res.extend(next);
+        if self.axiom != DEFAULT_AXIOM {
+            writeln!(f, "    %start {};", self.axiom)?;
         }
-        for t in &state.transitions {
-            if !t.is_nonterminal() {
-                res.insert(t.character);
-            } else {
-                let nextnext = self.followers_impl(machine, t.dest_id, visited, next);
-                let rec_fol = self.followers_impl(t.character, 0, visited, &nextnext);
-                res.extend(rec_fol);
-            }
+        if self.end_marker != DEFAULT_END_MARKER {
+            writeln!(f, "    %end '{}';", self.end_marker)?;
+        }
+        for (assoc, terminals) in self.precedence.declarations() {
+            let terminals: Vec<_> = terminals.iter().map(|t| t.to_string()).collect();
+            writeln!(f, "    {} {};", assoc.keyword(), terminals.join(" "))?;
         }
-        return res;
+        for m in &self.machines {
+            let header = match self.precedence.machines.get(&m.name) {
+                Some((t, _)) => format!(" %prec {}", t),
+                None => String::new()
+            };
+            m.write_mnet(f, "    ", &header)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Lookahead of the candidates of a pilot built without lookaheads.
+pub const NO_LOOKAHEAD: char = '\0';
+
+/// Lookahead of a candidate: a terminal, or a string of up to `k`
+/// terminals in an ELR(k) pilot.
+pub trait Lookahead: Clone + fmt::Debug + Eq + Hash + Ord {
+    /// The lookahead as printed, `None` when the pilot has none.
+    fn text(&self) -> Option<String>;
+}
+
+impl Lookahead for char {
+    fn text(&self) -> Option<String> {
+        (*self != NO_LOOKAHEAD).then(|| self.to_string())
     }
+}
 
-    fn followers(&self, machine: char, id: i32, next: HashSet<char>) -> HashSet<char> {
-        let mut visited: HashSet<(char, i32)> = HashSet::new();
-        return self.followers_impl(machine, id, &mut visited, &next);
+impl Lookahead for String {
+    fn text(&self) -> Option<String> {
+        Some(self.clone())
     }
 }
 
+/// Gives the lookaheads of the candidates added by the closure.
+pub trait ClosureLookaheads {
+    type Lookahead: Lookahead;
+
+    /// Lookaheads of the candidates spawned by a nonterminal arc reaching
+    /// state `id`, when `next` is the lookahead of the calling candidate,
+    /// in increasing order.
+    fn closure_lookaheads(&self, machine: char, id: i32, next: &Self::Lookahead) -> Vec<Self::Lookahead>;
+}
+
+/// Without an analysis the candidates carry no lookahead, as in LR(0).
+impl ClosureLookaheads for Option<&NetAnalysis> {
+    type Lookahead = char;
+
+    fn closure_lookaheads(&self, machine: char, id: i32, next: &char) -> Vec<char> {
+        let mut res: Vec<_> = match self {
+            Some(analysis) => analysis.lookaheads(machine, id, *next).into_iter().collect(),
+            None => vec![NO_LOOKAHEAD]
+        };
+        res.sort();
+        res
+    }
+}
 
 #[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Ord, PartialOrd)]
-pub struct Candidate {
+pub struct Candidate<L = char> {
     pub machine: char,
     pub state: i32,
-    pub lookahead: char,
-    pub is_final: bool
+    pub lookahead: L,
+    pub is_final: bool,
+    /// Added by the closure, as opposed to reached by a shift even when
+    /// the shift leads back to the initial state.
+    pub is_initial: bool
 }
 
-impl Candidate {
-    fn to_string(&self) -> String {
-        let state = if self.is_final {
-            format!("({}{})", self.state, self.machine)
-        } else {
-            format!("{}{}", self.state, self.machine)
-        };
-        format!("<{}, {}>", state, self.lookahead)
+impl<L: Lookahead> Candidate<L> {
+    /// Candidates at an initial state are added by the closure.
+    fn is_base(&self) -> bool {
+        !self.is_initial
     }
 
-    fn is_base(&self) -> bool {
-        self.state != 0
+    /// The candidate as printed in reports, with the state name given in
+    /// the net: `<(1S), a>` for a final candidate.
+    pub fn describe(&self, net: &MachineNet) -> String {
+        let state = net.state_label(self.machine, self.state);
+        let state = if self.is_final { format!("({})", state) } else { state };
+        match self.lookahead.text() {
+            None => format!("<{}>", state),
+            Some(lookahead) => format!("<{}, {}>", state, lookahead)
+        }
     }
 }
 
@@ -197,9 +308,16 @@ pub struct PilotTransition {
     pub candidate_map: Vec<(usize, usize)>
 }
 
+impl PilotTransition {
+    pub fn is_nonterminal(&self) -> bool {
+        self.character.is_ascii_uppercase()
+    }
+}
+
 pub struct ShiftReduceConflict {
     pub state_id: i32,
-    pub candidate_idx: usize
+    pub candidate_idx: usize,
+    pub edge: char
 }
 
 pub struct ReduceReduceConflict {
@@ -218,34 +336,37 @@ pub struct ConvergenceConflict {
 }
 
 #[derive(Debug, Clone)]
-pub struct PilotState {
+pub struct PilotState<L = char> {
     pub id: i32,
-    pub candidates: Vec<Candidate>,
-    pub transitions: Vec<PilotTransition>
+    pub candidates: Vec<Candidate<L>>,
+    pub transitions: Vec<PilotTransition>,
+    /// Final candidates whose reduction was removed by resolving a conflict.
+    pub dropped_reductions: Vec<usize>
 }
 
-impl PilotState {
-    pub fn base_set(&self) -> HashSet<&Candidate> {
+impl<L: Lookahead> PilotState<L> {
+    pub fn base_set(&self) -> HashSet<&Candidate<L>> {
         self.candidates.iter().filter(|x| x.is_base()).collect::<HashSet<_>>()
     }
     
-    pub fn is_equivalent(&self, other: &PilotState) -> bool {
+    pub fn is_equivalent(&self, other: &PilotState<L>) -> bool {
         let my_base = self.base_set();
         let other_base = other.base_set();
-        return my_base == other_base;
+        my_base == other_base
     }
 
-    pub fn shift_reduce_conflicts(&self) -> Vec<ShiftReduceConflict> {
-        let outgoing: HashSet<char> = self.transitions.iter().map(|trans| {
-            trans.character
-        }).collect();
-        self.candidates.iter().enumerate().filter_map(|(i, cand)| {
-            if cand.is_final && outgoing.contains(&cand.lookahead) {
-                Some(ShiftReduceConflict{state_id:self.id, candidate_idx:i})
-            } else {
-                None
-            }
-        }).collect()
+    /// Where each candidate of the shifted state `new` sits in this state,
+    /// when the two are equivalent.
+    fn merge_positions(&self, new: &PilotState<L>) -> Option<Vec<usize>> {
+        if !self.is_equivalent(new) {
+            return None;
+        }
+        new.candidates.iter().map(|c| self.candidates.iter().position(|x| x == c)).collect()
+    }
+
+    /// Whether candidate `idx` is final and its reduction was not dropped.
+    pub fn reduces(&self, idx: usize) -> bool {
+        self.candidates[idx].is_final && !self.dropped_reductions.contains(&idx)
     }
 
     pub fn reduce_reduce_conflicts(&self) -> Vec<ReduceReduceConflict> {
@@ -254,7 +375,7 @@ impl PilotState {
             for j in i+1 .. self.candidates.len() {
                 let ci = &self.candidates[i];
                 let cj = &self.candidates[j];
-                if ci.is_final && cj.is_final && ci.lookahead == cj.lookahead {
+                if self.reduces(i) && self.reduces(j) && ci.lookahead == cj.lookahead {
                     res.push(ReduceReduceConflict{state_id: self.id, candidate_1_idx:i, candidate_2_idx:j});
                 }
             }
@@ -283,13 +404,31 @@ impl PilotState {
     }
 }
 
+impl PilotState {
+    pub fn shift_reduce_conflicts(&self) -> Vec<ShiftReduceConflict> {
+        let outgoing: HashSet<char> = self.transitions.iter().map(|trans| {
+            trans.character
+        }).collect();
+        self.candidates.iter().enumerate().filter_map(|(i, cand)| {
+            if self.reduces(i) && outgoing.contains(&cand.lookahead) {
+                Some(ShiftReduceConflict{state_id:self.id, candidate_idx:i, edge:cand.lookahead})
+            } else {
+                None
+            }
+        }).collect()
+    }
+}
+
 #[derive(Debug)]
-pub struct Pilot {
-    pub states: Vec<PilotState>
+pub struct Pilot<L = char> {
+    pub states: Vec<PilotState<L>>,
+    /// Axiom and end marker of the net the pilot was built for.
+    pub axiom: char,
+    pub end_marker: char
 }
 
-impl Pilot {
-    fn lookup_state_mut(&mut self, id: i32) -> &mut PilotState {
+impl<L: Lookahead> Pilot<L> {
+    fn lookup_state_mut(&mut self, id: i32) -> &mut PilotState<L> {
         for s in &mut self.states {
             if s.id == id {
                 return s;
@@ -298,7 +437,7 @@ impl Pilot {
         panic!("state {id} does not exist");
     }
 
-    pub fn lookup_state(&self, id: i32) -> &PilotState {
+    pub fn lookup_state(&self, id: i32) -> &PilotState<L> {
         for s in &self.states {
             if s.id == id {
                 return s;
@@ -307,83 +446,128 @@ impl Pilot {
         panic!("state {id} does not exist");
     }
 
-    fn insert(&mut self, mut new: PilotState, net: &MachineNet) -> i32 {
+    /// Adds `new` unless an equivalent state exists, returning the id of
+    /// the state and where the candidates of `new` ended up in it.
+    fn insert<A: ClosureLookaheads<Lookahead = L>>(&mut self, mut new: PilotState<L>, net: &MachineNet, analysis: &A) -> (i32, Vec<usize>) {
         for s in &self.states {
-            if s.is_equivalent(&new) {
-                return s.id;
+            if let Some(positions) = s.merge_positions(&new) {
+                return (s.id, positions);
             }
         }
         let id = self.states.len() as i32;
+        let positions = (0..new.candidates.len()).collect();
         new.id = id;
-        closure(&mut new, net);
+        closure(&mut new, net, analysis);
         self.states.push(new);
-        return id;
+        (id, positions)
     }
 
-    pub fn print_shift_reduce_conflict(&self, c: &ShiftReduceConflict) {
+    pub fn print_reduce_reduce_conflict(&self, c: &ReduceReduceConflict, net: &MachineNet) {
         let s = c.state_id;
-        let candidate = &self.lookup_state(s).candidates[c.candidate_idx];
-        let c = candidate.to_string();
-        let edge = candidate.lookahead;
-        eprintln!("state I{s}: shift-reduce conflict between {c} and outgoing edge '{edge}'");
-    }
-
-    pub fn print_reduce_reduce_conflict(&self, c: &ReduceReduceConflict) {
-        let s = c.state_id;
-        let c1 = self.lookup_state(s).candidates[c.candidate_1_idx].to_string();
-        let c2 = self.lookup_state(s).candidates[c.candidate_2_idx].to_string();
+        let cand1 = &self.lookup_state(s).candidates[c.candidate_1_idx];
+        let cand2 = &self.lookup_state(s).candidates[c.candidate_2_idx];
+        let c1 = cand1.describe(net);
+        let c2 = cand2.describe(net);
         eprintln!("state I{s}: reduce-reduce conflict between {c1} and {c2}");
+        note_final_state(net, cand1);
+        note_final_state(net, cand2);
     }
-    
-    pub fn print_convergence_conflict(&self, c: &ConvergenceConflict) {
+
+    pub fn print_convergence_conflict(&self, c: &ConvergenceConflict, net: &MachineNet) {
         let s1 = c.state_1_id;
-        let c1 = self.lookup_state(s1).candidates[c.candidate_1_1_idx].to_string();
-        let c2 = self.lookup_state(s1).candidates[c.candidate_1_2_idx].to_string();
+        let cand1 = &self.lookup_state(s1).candidates[c.candidate_1_1_idx];
+        let cand2 = &self.lookup_state(s1).candidates[c.candidate_1_2_idx];
+        let c1 = cand1.describe(net);
+        let c2 = cand2.describe(net);
         let ts = c.transition_char;
         let s2 = c.state_2_id;
-        let c3 = self.lookup_state(s2).candidates[c.candidate_2_idx].to_string();
+        let c3 = self.lookup_state(s2).candidates[c.candidate_2_idx].describe(net);
         eprintln!("transition I{s1} -{ts}-> I{s2}: convergence conflict as both {c1} and {c2} shift to {c3}");
+        note_transition(net, cand1, ts);
+        note_transition(net, cand2, ts);
     }
+}
 
-    pub fn print_conflicts(&self) {
+impl Pilot {
+    pub fn print_shift_reduce_conflict(&self, c: &ShiftReduceConflict, net: &MachineNet) {
+        let s = c.state_id;
+        let candidate = &self.lookup_state(s).candidates[c.candidate_idx];
+        let edge = c.edge;
+        let c = candidate.describe(net);
+        eprintln!("state I{s}: shift-reduce conflict between {c} and outgoing edge '{edge}'");
+        note_final_state(net, candidate);
+        let mut noted: HashSet<(char, i32)> = HashSet::new();
+        for other in &self.lookup_state(s).candidates {
+            if noted.insert((other.machine, other.state)) {
+                note_transition(net, other, edge);
+            }
+        }
+    }
+
+    pub fn print_conflicts(&self, net: &MachineNet) {
+        let finder = counterexample::CounterexampleFinder::new(self, net);
         let mut n_confl = 0;
         for state in &self.states {
             let sr_confl = state.shift_reduce_conflicts();
-            for confl in &sr_confl {
-                self.print_shift_reduce_conflict(confl);
-                n_confl += 1;
-            }
             let rr_confl = state.reduce_reduce_conflicts();
-            for confl in &rr_confl {
-                self.print_reduce_reduce_conflict(confl);
-                n_confl += 1;
-            }
             let c_confl = state.convergence_conflicts();
-            for confl in &c_confl {
-                self.print_convergence_conflict(confl);
-                n_confl += 1;
-            }
+            n_confl += self.print_conflict_lists(net, &sr_confl, &rr_confl, &c_confl, Some(&finder));
         }
         if n_confl == 0 {
             eprintln!("no conflicts");
         }
     }
+
+    fn print_conflict_lists(&self, net: &MachineNet, sr_confl: &[ShiftReduceConflict], rr_confl: &[ReduceReduceConflict], c_confl: &[ConvergenceConflict], finder: Option<&counterexample::CounterexampleFinder>) -> usize {
+        for confl in sr_confl {
+            self.print_shift_reduce_conflict(confl, net);
+            if let Some(ex) = finder.and_then(|f| f.shift_reduce(confl)) {
+                ex.print();
+            }
+        }
+        for confl in rr_confl {
+            self.print_reduce_reduce_conflict(confl, net);
+            if let Some(ex) = finder.and_then(|f| f.reduce_reduce(confl)) {
+                ex.print();
+            }
+        }
+        for confl in c_confl {
+            self.print_convergence_conflict(confl, net);
+            if let Some(ex) = finder.and_then(|f| f.convergence(confl)) {
+                ex.print();
+            }
+        }
+        sr_confl.len() + rr_confl.len() + c_confl.len()
+    }
+}
+
+fn note_final_state<L>(net: &MachineNet, c: &Candidate<L>) {
+    let state = net.lookup_state(c.machine, c.state);
+    state.location.emit_note(&format!("final state {} defined here", net.state_label(c.machine, c.state)));
 }
 
+fn note_transition<L>(net: &MachineNet, c: &Candidate<L>, character: char) {
+    let state = net.lookup_state(c.machine, c.state);
+    if let Some(t) = state.try_lookup_transition(character) {
+        t.location.emit_note(&format!("arc {} -{}-> {} defined here", net.state_label(c.machine, c.state), character, net.state_label(c.machine, t.dest_id)));
+    }
+}
 
-fn closure(state: &mut PilotState, net: &MachineNet) {
+/// Closes `state` under the nonterminal arcs of its candidates. A closure
+/// candidate equal to a base one but for `is_initial` is kept apart: the
+/// first starts a new call of the machine, the second continues one.
+fn closure<A: ClosureLookaheads>(state: &mut PilotState<A::Lookahead>, net: &MachineNet, analysis: &A) {
     let mut candidate_id: usize = 0;
     while candidate_id < state.candidates.len() {
-        let c = state.candidates[candidate_id];
+        let c = state.candidates[candidate_id].clone();
         let mstate = net.lookup_state(c.machine, c.state);
         for t in &mstate.transitions {
             if !t.is_nonterminal() {
                 continue;
             }
-            let ini = net.followers(c.machine, t.dest_id, HashSet::from([c.lookahead]));
-            for ch in ini {
-                let dest_state = net.lookup_state(t.character, 0);
-                let c2 = Candidate{machine:t.character, state:0, lookahead:ch, is_final:dest_state.is_final};
+            for ch in analysis.closure_lookaheads(c.machine, t.dest_id, &c.lookahead) {
+                let dest_state = net.lookup_machine(t.character).initial_state();
+                let c2 = Candidate{machine:t.character, state:dest_state.id, lookahead:ch, is_final:dest_state.is_final, is_initial:true};
                 if !state.candidates.contains(&c2) {
                     state.candidates.push(c2);
                 }
@@ -393,7 +577,7 @@ fn closure(state: &mut PilotState, net: &MachineNet) {
     }
 }
 
-fn collect_transitions(state: &PilotState, net: &MachineNet) -> Vec<char> {
+fn collect_transitions<L>(state: &PilotState<L>, net: &MachineNet) -> Vec<char> {
     let mut res: HashSet<char> = HashSet::new();
     for c in &state.candidates {
         let mstate = net.lookup_state(c.machine, c.state);
@@ -401,25 +585,25 @@ fn collect_transitions(state: &PilotState, net: &MachineNet) -> Vec<char> {
             res.insert(t.character);
         }
     }
-    let mut vec_res = Vec::from_iter(res.into_iter());
+    let mut vec_res = Vec::from_iter(res);
     vec_res.sort();
-    return vec_res;
+    vec_res
 }
 
-fn shift_candidate(c: &Candidate, net: &MachineNet, next: char) -> Option<Candidate> {
+fn shift_candidate<L: Clone>(c: &Candidate<L>, net: &MachineNet, next: char) -> Option<Candidate<L>> {
     let mstate = net.lookup_state(c.machine, c.state);
     for t in &mstate.transitions {
         if t.character == next {
             let dest_state = net.lookup_state(c.machine, t.dest_id);
-            return Some(Candidate{machine:c.machine, state:t.dest_id, lookahead:c.lookahead, is_final:dest_state.is_final});
+            return Some(Candidate{machine:c.machine, state:t.dest_id, lookahead:c.lookahead.clone(), is_final:dest_state.is_final, is_initial:false});
         }
     }
-    return None;
+    None
 }
 
-fn shift(state: &PilotState, net: &MachineNet, character: char) -> (PilotTransition, PilotState) {
+fn shift<L: Lookahead>(state: &PilotState<L>, net: &MachineNet, character: char) -> (PilotTransition, PilotState<L>) {
     let mut orig_states: HashSet<(char, i32)> = HashSet::new();
-    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut candidates: Vec<Candidate<L>> = Vec::new();
     let mut candidate_map: Vec<(usize, usize)> = Vec::new();
     for (i, c) in state.candidates.iter().enumerate() {
         if let Some(new) = shift_candidate(c, net, character) {
@@ -434,16 +618,21 @@ fn shift(state: &PilotState, net: &MachineNet, character: char) -> (PilotTransit
         }
     }
     let multiplicity = orig_states.len() as i32;
-    (PilotTransition{character, dest_id:-1, multiplicity, candidate_map}, PilotState{id:-1, candidates, transitions:vec![]})
+    (PilotTransition{character, dest_id:-1, multiplicity, candidate_map}, PilotState{id:-1, candidates, transitions:vec![], dropped_reductions:vec![]})
 }
 
 pub fn create_pilot(net: &MachineNet) -> Pilot {
-    let first_state = net.lookup_state('S', 0);
-    let init_candidate = Candidate{machine:'S', state:0, lookahead:'$', is_final:first_state.is_final};
-    let init_state = PilotState{id:0, candidates:vec![init_candidate], transitions:vec![]};
-    let mut pilot = Pilot{states: vec![]};
+    let analysis = NetAnalysis::new(net);
+    build_pilot(net, &Some(&analysis), net.end_marker)
+}
 
-    let mut worklist = VecDeque::from([pilot.insert(init_state, net)]);
+fn build_pilot<A: ClosureLookaheads>(net: &MachineNet, analysis: &A, end_lookahead: A::Lookahead) -> Pilot<A::Lookahead> {
+    let first_state = net.lookup_machine(net.axiom).initial_state();
+    let init_candidate = Candidate{machine:net.axiom, state:first_state.id, lookahead:end_lookahead, is_final:first_state.is_final, is_initial:true};
+    let init_state = PilotState{id:0, candidates:vec![init_candidate], transitions:vec![], dropped_reductions:vec![]};
+    let mut pilot = Pilot{states: vec![], axiom: net.axiom, end_marker: net.end_marker};
+
+    let mut worklist = VecDeque::from([pilot.insert(init_state, net, analysis).0]);
     let mut visited: HashSet<i32> = HashSet::new();
     while !worklist.is_empty() {
         let state_id = worklist.pop_front().unwrap();
@@ -455,16 +644,116 @@ pub fn create_pilot(net: &MachineNet) -> Pilot {
         let state = pilot.lookup_state(state_id);
         let future_xions = collect_transitions(state, net);
         let shifts: Vec<_> = future_xions.into_iter().map(|c| {
-            shift(&state, net, c)
+            shift(state, net, c)
         }).collect();
         let xions: Vec<_> = shifts.into_iter().map(|(mut trans, maybe_new_state)| {
-            let id = pilot.insert(maybe_new_state, net);
+            let (id, positions) = pilot.insert(maybe_new_state, net, analysis);
             trans.dest_id = id;
+            // An equivalent state may list its candidates in another order
+            for (_, d) in trans.candidate_map.iter_mut() {
+                *d = positions[*d];
+            }
             trans
         }).collect();
         worklist.extend(xions.iter().map(|xion| xion.dest_id));
         pilot.lookup_state_mut(state_id).transitions = xions;
     }
 
-    return pilot;
+    pilot
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    #[test]
+    fn test_closure_lookaheads() {
+        // The lookahead of X must come from the first B call even though
+        // B is also reached while computing the initials of state 1S.
+        let net = load_net(r"./tests/lookahead_context.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let x = Candidate{machine:'X', state:0, lookahead:'b', is_final:false, is_initial:true};
+        assert!(pilot.lookup_state(0).candidates.contains(&x));
+    }
+
+    #[test]
+    fn test_shift_to_initial_state() {
+        // The arc a back to 0A gives base candidates at an initial state,
+        // which must keep the states reading a after x and after A apart.
+        let net = load_net(r"./tests/shift_to_initial.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let mut lookaheads: Vec<char> = pilot.states.iter().filter(|s| {
+            s.transitions.iter().any(|t| t.character == 'a' && t.dest_id == s.id)
+        }).map(|s| {
+            let base: Vec<_> = s.base_set().into_iter().collect();
+            assert_eq!((base.len(), base[0].machine, base[0].state), (1, 'A', 0));
+            base[0].lookahead
+        }).collect();
+        lookaheads.sort();
+        assert_eq!(lookaheads, vec![net.end_marker, 'c']);
+    }
+
+    #[test]
+    fn test_loop_on_initial_state() {
+        // S -> S* a: after S, 0S both continues the outer call and starts
+        // a nested one, and the two converge on every arc
+        let net = load_net(r"./tests/cursed.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let conflicts: Vec<_> = pilot.states.iter().flat_map(|s| s.convergence_conflicts()).map(|c| {
+            (c.state_1_id, c.transition_char, c.state_2_id)
+        }).collect();
+        assert_eq!(conflicts, vec![(1, 'S', 1), (1, 'a', 2)]);
+    }
+
+    #[test]
+    fn test_axiom_and_end_marker() {
+        let net = load_net(r"./tests/expr_entry.txt").unwrap();
+        assert_eq!((net.axiom, net.end_marker), ('E', '#'));
+        let pilot = create_pilot(&net);
+        let start = Candidate{machine:'E', state:0, lookahead:'#', is_final:false, is_initial:true};
+        assert!(pilot.lookup_state(0).candidates.contains(&start));
+        assert!(pilot.states.iter().all(|s| s.shift_reduce_conflicts().is_empty()));
+        assert_eq!(NetAnalysis::new(&net).follow('E'), &HashSet::from(['#', 'c']));
+
+        let net = net.with_axiom('T');
+        assert!(net.validate());
+        let pilot = create_pilot(&net);
+        assert_eq!(pilot.lookup_state(0).candidates[0].machine, 'T');
+        assert!(!NetAnalysis::new(&net).follow('E').contains(&'#'));
+        assert!(!net.with_axiom('X').validate());
+    }
+
+    #[test]
+    fn test_named_states() {
+        // Same net as elr_mnet_book-4.15.txt with names not starting at 0.
+        let net = load_net(r"./tests/named_states.txt").unwrap();
+        let numbered = load_net(r"./tests/elr_mnet_book-4.15.txt").unwrap();
+        assert_eq!(net.initial_id('A'), 6);
+        assert!(net.lookup_machine('S').try_lookup_state_by_name("q_start").unwrap().is_initial);
+        assert_eq!(net.state_label('B', net.initial_id('B')), "E1B");
+        let pilot = create_pilot(&net);
+        assert_eq!(pilot.states.len(), create_pilot(&numbered).states.len());
+        assert!(pilot.lookup_state(0).base_set().is_empty());
+        assert!(pilot.states[1..].iter().all(|s| !s.base_set().is_empty()));
+        assert!(pilot.to_dot(&net).contains("q_start<sub>S</sub>"));
+        assert!(net.to_string().contains("a -> E2;"));
+    }
+
+    #[test]
+    fn test_conflict_locations() {
+        // The reduction of B at 0B clashes with its arc -b-> on line 21.
+        let net = load_net(r"./tests/dangling_else.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let (state, conflict) = pilot.states.iter().flat_map(|s| {
+            s.shift_reduce_conflicts().into_iter().map(move |c| (s, c))
+        }).next().unwrap();
+        let c = &state.candidates[conflict.candidate_idx];
+        assert_eq!((c.machine, c.state, conflict.edge), ('B', 0, 'b'));
+        let s = net.lookup_state(c.machine, c.state);
+        assert_eq!((s.location.row, s.location.col), (17, 8));
+        let t = s.try_lookup_transition(conflict.edge).unwrap();
+        assert_eq!((t.location.row, t.location.col), (20, 12));
+        assert_eq!(t.location.file.as_deref(), Some("./tests/dangling_else.txt"));
+    }
 }
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use crate::analysis::NetAnalysis;
+use crate::analysis::shortest_strings;
+use crate::analysis::shortest_strings_by_first;
+use crate::elr_pilot::*;
+
+/// Concrete input exhibiting a conflict: a shortest terminal prefix driving
+/// the pilot to the conflicting state, the lookahead on which the conflict
+/// arises, and the two competing ways of continuing the parse.
+pub struct Counterexample {
+    pub prefix: String,
+    pub lookahead: char,
+    /// The action each parse takes at the conflict, and the rest of a
+    /// sentence that the parse completes after the prefix.
+    pub parses: [(String, String); 2]
+}
+
+impl Counterexample {
+    pub fn print(&self) {
+        eprintln!("  shortest prefix: {}, lookahead: {}", spaced(&self.prefix), self.lookahead);
+        for (i, (action, rest)) in self.parses.iter().enumerate() {
+            eprintln!("  parse {}: {} ⋅ [{}] {}", i + 1, spaced(&self.prefix), action, spaced(rest));
+        }
+    }
+}
+
+fn spaced(s: &str) -> String {
+    if s.is_empty() {
+        "ε".to_string()
+    } else {
+        s.chars().map(|c| c.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+pub struct CounterexampleFinder<'a> {
+    pilot: &'a Pilot,
+    net: &'a MachineNet,
+    analysis: NetAnalysis,
+    strings: HashMap<(char, i32), String>,
+    strings_by_first: HashMap<(char, i32, char), String>,
+    /// Shortest prefix reaching each pilot state, with the labels of the
+    /// pilot edges taken.
+    prefixes: HashMap<i32, (String, Vec<char>)>
+}
+
+impl<'a> CounterexampleFinder<'a> {
+    pub fn new(pilot: &'a Pilot, net: &'a MachineNet) -> CounterexampleFinder<'a> {
+        let strings = shortest_strings(net);
+        let strings_by_first = shortest_strings_by_first(net, &strings);
+        let prefixes = shortest_prefixes(pilot, net, &strings);
+        CounterexampleFinder{pilot, net, analysis: NetAnalysis::new(net), strings, strings_by_first, prefixes}
+    }
+
+    /// Shortest terminal string driving the pilot from I0 to `state_id`.
+    pub fn prefix(&self, state_id: i32) -> Option<&String> {
+        self.prefixes.get(&state_id).map(|(prefix, _)| prefix)
+    }
+
+    /// The machines waiting for candidate `idx` of `state_id` to reduce,
+    /// innermost first, with the state each returns to, along the path of
+    /// the shortest prefix. Base candidates are followed back through the
+    /// pilot edges, closure candidates to a candidate calling them.
+    fn callers(&self, state_id: i32, idx: usize) -> Option<Vec<(char, i32)>> {
+        let path = &self.prefixes.get(&state_id)?.1;
+        let mut states = vec![0];
+        for ch in path {
+            let s = self.pilot.lookup_state(*states.last().unwrap());
+            states.push(s.transitions.iter().find(|t| t.character == *ch)?.dest_id);
+        }
+        let mut res: Vec<(char, i32)> = Vec::new();
+        let (mut k, mut idx) = (path.len(), idx);
+        while k > 0 || idx > 0 {
+            let state = self.pilot.lookup_state(states[k]);
+            let c = &state.candidates[idx];
+            if c.is_base() {
+                let t = self.pilot.lookup_state(states[k - 1]).transitions.iter().find(|t| t.character == path[k - 1])?;
+                idx = t.candidate_map.iter().find(|(_, d)| *d == idx)?.0;
+                k -= 1;
+                continue;
+            }
+            // The closure only appends, so the caller comes before
+            let (j, ret) = state.candidates[..idx].iter().enumerate().find_map(|(j, p)| {
+                let t = self.net.lookup_state(p.machine, p.state).try_lookup_transition(c.machine)?;
+                let lookaheads = self.analysis.lookaheads(p.machine, t.dest_id, p.lookahead);
+                lookaheads.contains(&c.lookahead).then_some((j, t.dest_id))
+            })?;
+            res.push((state.candidates[j].machine, ret));
+            idx = j;
+        }
+        Some(res)
+    }
+
+    /// Shortest string the `callers` read once the innermost machine has
+    /// reduced, starting with `lookahead`, or empty on the end marker.
+    fn continuation(&self, lookahead: char, callers: &[(char, i32)]) -> Option<String> {
+        for (i, (machine, state)) in callers.iter().enumerate() {
+            if let Some(head) = self.strings_by_first.get(&(*machine, *state, lookahead)) {
+                let mut res = head.clone();
+                for (m, s) in &callers[i + 1 ..] {
+                    res.push_str(self.strings.get(&(*m, *s))?);
+                }
+                return Some(res);
+            }
+            // Callers before the one reading the lookahead must read nothing
+            if !self.strings.get(&(*machine, *state))?.is_empty() {
+                return None;
+            }
+        }
+        (lookahead == self.net.end_marker).then(String::new)
+    }
+
+    /// Terminal string read when the pilot takes an edge on `character`.
+    fn label(&self, character: char) -> Option<String> {
+        if character.is_ascii_uppercase() {
+            self.strings.get(&(character, self.net.initial_id(character))).cloned()
+        } else {
+            Some(character.to_string())
+        }
+    }
+
+    /// Reduction of candidate `idx` of `state_id`, then the rest of the
+    /// sentence read by its callers.
+    fn reduce_completion(&self, state_id: i32, idx: usize) -> Option<(String, String)> {
+        let c = &self.pilot.lookup_state(state_id).candidates[idx];
+        let rest = self.continuation(c.lookahead, &self.callers(state_id, idx)?)?;
+        Some((format!("reduce {}", c.machine), rest))
+    }
+
+    /// Shift of `character` from candidate `idx` of `state_id`, then the
+    /// rest of its machine and of its callers.
+    fn shift_completion(&self, state_id: i32, idx: usize, character: char) -> Option<(String, String)> {
+        let c = &self.pilot.lookup_state(state_id).candidates[idx];
+        let dest = shift_candidate(c, self.net, character)?;
+        let mut rest = self.label(character)?;
+        rest.push_str(self.strings.get(&(dest.machine, dest.state))?);
+        rest.push_str(&self.continuation(c.lookahead, &self.callers(state_id, idx)?)?);
+        let from = self.net.state_label(c.machine, c.state);
+        Some((format!("shift {} from {}", character, from), rest))
+    }
+
+    pub fn shift_reduce(&self, c: &ShiftReduceConflict) -> Option<Counterexample> {
+        let state = self.pilot.lookup_state(c.state_id);
+        let shift = (0 .. state.candidates.len()).find_map(|i| self.shift_completion(c.state_id, i, c.edge))?;
+        Some(Counterexample{
+            prefix: self.prefix(c.state_id)?.clone(),
+            lookahead: c.edge,
+            parses: [self.reduce_completion(c.state_id, c.candidate_idx)?, shift]
+        })
+    }
+
+    pub fn reduce_reduce(&self, c: &ReduceReduceConflict) -> Option<Counterexample> {
+        let state = self.pilot.lookup_state(c.state_id);
+        Some(Counterexample{
+            prefix: self.prefix(c.state_id)?.clone(),
+            lookahead: state.candidates[c.candidate_1_idx].lookahead,
+            parses: [self.reduce_completion(c.state_id, c.candidate_1_idx)?, self.reduce_completion(c.state_id, c.candidate_2_idx)?]
+        })
+    }
+
+    pub fn convergence(&self, c: &ConvergenceConflict) -> Option<Counterexample> {
+        let state = self.pilot.lookup_state(c.state_1_id);
+        let shift = |idx: usize| self.shift_completion(c.state_1_id, idx, c.transition_char);
+        Some(Counterexample{
+            prefix: self.prefix(c.state_1_id)?.clone(),
+            lookahead: state.candidates[c.candidate_1_1_idx].lookahead,
+            parses: [shift(c.candidate_1_1_idx)?, shift(c.candidate_1_2_idx)?]
+        })
+    }
+}
+
+fn shortest_prefixes(pilot: &Pilot, net: &MachineNet, strings: &HashMap<(char, i32), String>) -> HashMap<i32, (String, Vec<char>)> {
+    let mut res: HashMap<i32, (String, Vec<char>)> = HashMap::from([(0, (String::new(), vec![]))]);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for s in &pilot.states {
+            let Some((prefix, path)) = res.get(&s.id).cloned() else {
+                continue;
+            };
+            for t in &s.transitions {
+                let label = if t.is_nonterminal() {
+                    match strings.get(&(t.character, net.initial_id(t.character))) {
+                        Some(label) => label.clone(),
+                        None => continue
+                    }
+                } else {
+                    t.character.to_string()
+                };
+                let new = prefix.clone() + &label;
+                if res.get(&t.dest_id).is_none_or(|(cur, _)| new.len() < cur.len()) {
+                    let mut new_path = path.clone();
+                    new_path.push(t.character);
+                    res.insert(t.dest_id, (new, new_path));
+                    changed = true;
+                }
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use crate::elr_pilot::*;
+    use crate::sentences::LanguageSlices;
+    use super::Counterexample;
+    use super::CounterexampleFinder;
+
+    fn parses(ex: &Counterexample) -> Vec<(&str, &str)> {
+        ex.parses.iter().map(|(action, rest)| (action.as_str(), rest.as_str())).collect()
+    }
+
+    /// Both parses must complete the prefix into a sentence of the net.
+    fn assert_derivable(net: &MachineNet, ex: &Counterexample) {
+        for (_, rest) in &ex.parses {
+            let sentence = ex.prefix.clone() + rest;
+            let slices = LanguageSlices::new(net, sentence.len());
+            assert!(slices.sentences_of_length(sentence.len()).contains(&sentence), "{} is not a sentence", sentence);
+        }
+    }
+
+    #[test]
+    fn test_shift_reduce_counterexample() {
+        let net = load_net(r"./tests/dangling_else.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let finder = CounterexampleFinder::new(&pilot, &net);
+        let confl: Vec<_> = pilot.states.iter().flat_map(|s| s.shift_reduce_conflicts()).collect();
+        assert_eq!(confl.len(), 1);
+        let ex = finder.shift_reduce(&confl[0]).unwrap();
+        assert_eq!((ex.prefix.as_str(), ex.lookahead), ("aac", 'b'));
+        assert_eq!(parses(&ex), vec![("reduce B", "bc"), ("shift b from 0B", "bc")]);
+        assert_derivable(&net, &ex);
+    }
+
+    #[test]
+    fn test_reduce_reduce_counterexample() {
+        let net = load_net(r"./tests/elr_mnet_book-4.15.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let finder = CounterexampleFinder::new(&pilot, &net);
+        let confl: Vec<_> = pilot.states.iter().flat_map(|s| s.reduce_reduce_conflicts()).collect();
+        assert_eq!(confl.len(), 1);
+        let ex = finder.reduce_reduce(&confl[0]).unwrap();
+        assert_eq!(ex.prefix, "aaaab");
+        assert_eq!(ex.lookahead, 'b');
+        assert_eq!(parses(&ex), vec![("reduce B", "b"), ("reduce A", "bbb")]);
+        assert_derivable(&net, &ex);
+    }
+
+    #[test]
+    fn test_convergence_counterexample() {
+        let net = load_net(r"./tests/elr_mnet_book-4.16.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let finder = CounterexampleFinder::new(&pilot, &net);
+        let confl: Vec<_> = pilot.states.iter().flat_map(|s| s.convergence_conflicts()).collect();
+        assert_eq!(confl.len(), 1);
+        let ex = finder.convergence(&confl[0]).unwrap();
+        assert_eq!((ex.prefix.as_str(), ex.lookahead), ("aab", 'e'));
+        assert_eq!(parses(&ex), vec![("shift c from 2S", "ce"), ("shift c from 4S", "cee")]);
+        assert_derivable(&net, &ex);
+    }
+}
@@ -2,23 +2,24 @@ use crate::elr_pilot::*;
 
 struct MergedCandidate {
     machine: char,
-    state: i32,
-    lookaheads: Vec<char>,
-    is_final: bool
+    /// Name of the machine state, as given in the net.
+    name: String,
+    lookaheads: Vec<String>,
+    is_final: bool,
+    is_initial: bool
 }
 
 impl MergedCandidate {
     fn to_dot_label_html(&self) -> String {
-        let look_strs: Vec<_> = self.lookaheads.iter().map(|c| c.to_string()).collect();
-        let look_str = look_strs.join(", ");
-        let state = format!("{}<sub>{}</sub>", self.state, self.machine);
+        let look_str = self.lookaheads.join(", ");
+        let state = format!("{}<sub>{}</sub>", self.name, self.machine);
         let state = if self.is_final { format!("({})", state) } else { state };
         format!("<tr><td sides=\"ltb\">{state}</td><td sides=\"trb\">{look_str}</td></tr>")
     }
 }
 
-impl PilotState {
-    fn merged_candidates(&self) -> Vec<MergedCandidate> {
+impl<L: Lookahead> PilotState<L> {
+    fn merged_candidates(&self, net: &MachineNet, end_marker: char) -> Vec<MergedCandidate> {
         let mut states: Vec<(char, i32)> = self.candidates.iter().map(|c| {
             (c.machine, c.state)
         }).collect();
@@ -28,29 +29,31 @@ impl PilotState {
             let raw_candidates: Vec<_> = self.candidates.iter().filter(|c| {
                 c.machine == machine && c.state == state
             }).collect();
-            let mut lookaheads: Vec<char> = raw_candidates.iter().map(|c| {
-                if c.lookahead == '$' { '⊣' } else { c.lookahead }
+            let mut lookaheads: Vec<String> = raw_candidates.iter().filter_map(|c| {
+                c.lookahead.text().map(|text| text.replace(end_marker, "⊣"))
             }).collect();
             lookaheads.sort();
             let is_final: bool = raw_candidates[0].is_final;
-            MergedCandidate{machine, state, lookaheads, is_final}
+            let is_initial: bool = raw_candidates[0].is_initial;
+            let name = net.lookup_machine(machine).state_name(state);
+            MergedCandidate{machine, name, lookaheads, is_final, is_initial}
         }).collect()
     }
 
-    fn to_dot(&self) -> String {
+    fn to_dot(&self, net: &MachineNet, end_marker: char) -> String {
         let mut res: Vec<String> = Vec::new();
         res.push(format!("  i{} [label=<", self.id));
 
-        let merged = self.merged_candidates();
+        let merged = self.merged_candidates(net, end_marker);
         let base: Vec<_> = merged.iter().filter_map(|c| {
-            if c.state != 0 {
+            if !c.is_initial {
                 Some(format!("    {}", c.to_dot_label_html()))
             } else {
                 None
             }
         }).collect();
         let closure: Vec<_> = merged.iter().filter_map(|c| {
-            if c.state == 0 {
+            if c.is_initial {
                 Some(format!("    {}", c.to_dot_label_html()))
             } else {
                 None
@@ -59,7 +62,7 @@ impl PilotState {
 
         let sep_border_top = if base.is_empty() { "t" } else { "" };
         let sep_border_bot = if closure.is_empty() { "b" } else { "" };
-        let sep_border_sides = if sep_border_bot != "" || sep_border_top != "" {
+        let sep_border_sides = if !sep_border_bot.is_empty() || !sep_border_top.is_empty() {
             format!("sides=\"{}{}\"", sep_border_top, sep_border_bot)
         } else {
             "border=\"0\"".to_string()
@@ -89,13 +92,55 @@ impl PilotState {
     }
 }
 
-impl Pilot {
-    pub fn to_dot(&self) -> String {
+impl<L: Lookahead> Pilot<L> {
+    pub fn to_dot(&self, net: &MachineNet) -> String {
         let header = "digraph {\n  node [shape=\"plain\", forcelabels=true];\n";
         let states = self.states.iter().map(|s| {
-            s.to_dot()
+            s.to_dot(net, self.end_marker)
         }).collect::<Vec<_>>().join("\n");
         let trailer = "\n}";
         format!("{}{}{}", header, states, trailer)
     }
 }
+
+impl MachineNet {
+    /// The net in DOT format, a cluster per machine. Initial states get an
+    /// entry arrow and final states a double circle; nonterminal arcs are
+    /// bold. With `calls`, a dashed edge goes from every state with a call
+    /// arc to the initial state of the called machine.
+    pub fn to_dot(&self, calls: bool) -> String {
+        let node = |machine: char, id: i32| format!("\"{}{}\"", machine, id);
+        let mut res: Vec<String> = vec!["digraph {".to_string(), "  rankdir=LR;".to_string()];
+        for m in &self.machines {
+            res.push(format!("  subgraph cluster_{} {{", m.name));
+            res.push(format!("    label=\"{}\";", m.name));
+            for s in &m.states {
+                let shape = if s.is_final { "doublecircle" } else { "circle" };
+                res.push(format!("    {} [label=\"{}\", shape={}];", node(m.name, s.id), s.name, shape));
+                if s.is_initial {
+                    res.push(format!("    \"{}_entry\" [shape=point, style=invis];", m.name));
+                    res.push(format!("    \"{}_entry\" -> {};", m.name, node(m.name, s.id)));
+                }
+            }
+            for s in &m.states {
+                for t in &s.transitions {
+                    let style = if t.is_nonterminal() { ", style=bold, color=blue, fontcolor=blue" } else { "" };
+                    res.push(format!("    {} -> {} [label=\"{}\"{}];", node(m.name, s.id), node(m.name, t.dest_id), t.character, style));
+                }
+            }
+            res.push("  }".to_string());
+        }
+        if calls {
+            for m in &self.machines {
+                for s in &m.states {
+                    for t in s.transitions.iter().filter(|t| t.is_nonterminal()) {
+                        let callee = node(t.character, self.initial_id(t.character));
+                        res.push(format!("  {} -> {} [style=dashed, color=gray, constraint=false];", node(m.name, s.id), callee));
+                    }
+                }
+            }
+        }
+        res.push("}".to_string());
+        res.join("\n")
+    }
+}
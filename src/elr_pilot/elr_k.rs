@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+use crate::analysis::KNetAnalysis;
+use crate::analysis::concat_k;
+use crate::elr_pilot::*;
+
+/// Candidates of an ELR(k) pilot carry a string of up to `k` terminals,
+/// shorter only when it ends with the end marker.
+impl ClosureLookaheads for KNetAnalysis {
+    type Lookahead = String;
+
+    fn closure_lookaheads(&self, machine: char, id: i32, next: &String) -> Vec<String> {
+        let mut res: Vec<_> = self.lookaheads(machine, id, next).into_iter().collect();
+        res.sort();
+        res
+    }
+}
+
+/// Shift-reduce conflict of an ELR(k) pilot: the lookahead of a final
+/// candidate is also a possible `k`-lookahead after shifting `edge`.
+pub struct KShiftReduceConflict {
+    pub state_id: i32,
+    pub candidate_idx: usize,
+    pub edge: char,
+    pub lookahead: String
+}
+
+impl PilotState<String> {
+    /// The `k`-lookaheads seen right before shifting `edge`, over every
+    /// candidate with a terminal arc labelled `edge`.
+    pub fn shift_lookaheads(&self, net: &MachineNet, analysis: &KNetAnalysis, edge: char) -> HashSet<String> {
+        let mut res: HashSet<String> = HashSet::new();
+        let head = HashSet::from([edge.to_string()]);
+        for c in &self.candidates {
+            if let Some(t) = net.lookup_state(c.machine, c.state).try_lookup_transition(edge) {
+                let tail = analysis.lookaheads(c.machine, t.dest_id, &c.lookahead);
+                res.extend(concat_k(analysis.k, analysis.end_marker, &head, &tail));
+            }
+        }
+        res
+    }
+
+    pub fn k_shift_reduce_conflicts(&self, net: &MachineNet, analysis: &KNetAnalysis) -> Vec<KShiftReduceConflict> {
+        let mut res: Vec<KShiftReduceConflict> = Vec::new();
+        for t in self.transitions.iter().filter(|t| !t.is_nonterminal()) {
+            let shifts = self.shift_lookaheads(net, analysis, t.character);
+            for (i, cand) in self.candidates.iter().enumerate() {
+                if cand.is_final && shifts.contains(&cand.lookahead) {
+                    res.push(KShiftReduceConflict{state_id: self.id, candidate_idx: i, edge: t.character, lookahead: cand.lookahead.clone()});
+                }
+            }
+        }
+        res
+    }
+}
+
+impl Pilot<String> {
+    /// Prints every conflict and returns how many were found; none means
+    /// the net is ELR(k).
+    pub fn print_k_conflicts(&self, net: &MachineNet, analysis: &KNetAnalysis) -> usize {
+        let mut n_confl = 0;
+        for state in &self.states {
+            let s = state.id;
+            for c in state.k_shift_reduce_conflicts(net, analysis) {
+                let cand = &state.candidates[c.candidate_idx];
+                eprintln!("state I{s}: shift-reduce conflict between {} and outgoing edge '{}' on lookahead {}", cand.describe(net), c.edge, c.lookahead);
+                note_final_state(net, cand);
+                let mut noted: HashSet<(char, i32)> = HashSet::new();
+                for other in &state.candidates {
+                    if noted.insert((other.machine, other.state)) {
+                        note_transition(net, other, c.edge);
+                    }
+                }
+                n_confl += 1;
+            }
+            for c in state.reduce_reduce_conflicts() {
+                self.print_reduce_reduce_conflict(&c, net);
+                n_confl += 1;
+            }
+            for c in state.convergence_conflicts() {
+                self.print_convergence_conflict(&c, net);
+                n_confl += 1;
+            }
+        }
+        if n_confl == 0 {
+            eprintln!("no conflicts");
+        }
+        n_confl
+    }
+}
+
+/// Builds the ELR(k) pilot of the net. With `k` equal to 1 it has the same
+/// states as the one built by `create_pilot`.
+pub fn create_k_pilot(net: &MachineNet, analysis: &KNetAnalysis) -> Pilot<String> {
+    build_pilot(net, analysis, net.end_marker.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    fn conflicts(net: &MachineNet, k: usize) -> usize {
+        let analysis = KNetAnalysis::new(net, k);
+        let pilot = create_k_pilot(net, &analysis);
+        pilot.print_k_conflicts(net, &analysis)
+    }
+
+    #[test]
+    fn test_elr_2_resolves_conflict() {
+        let net = load_net(r"./tests/elr_2.txt").unwrap();
+        assert!(conflicts(&net, 1) > 0);
+        assert_eq!(conflicts(&net, 2), 0);
+    }
+
+    #[test]
+    fn test_k_1_matches_elr_1() {
+        let net = load_net(r"./tests/elr_mnet_book-4.15.txt").unwrap();
+        let analysis = KNetAnalysis::new(&net, 1);
+        let pilot = create_k_pilot(&net, &analysis);
+        assert_eq!(pilot.states.len(), create_pilot(&net).states.len());
+    }
+}
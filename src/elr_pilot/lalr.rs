@@ -0,0 +1,126 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use crate::elr_pilot::*;
+
+impl PilotState {
+    /// Base machine states of the pilot state, lookaheads ignored.
+    pub fn kernel(&self) -> BTreeSet<(char, i32)> {
+        self.base_set().into_iter().map(|c| (c.machine, c.state)).collect()
+    }
+}
+
+/// A pilot whose states with equal kernels were merged, LALR-style.
+pub struct Compaction {
+    pub pilot: Pilot,
+    /// Original states merged into each new state, indexed by new id.
+    pub groups: Vec<Vec<i32>>,
+    /// Reduce-reduce conflicts that no original state of the group had.
+    pub new_conflicts: Vec<ReduceReduceConflict>
+}
+
+impl Pilot {
+    /// Merges the states whose kernels are equal when lookaheads are
+    /// ignored, taking the union of their candidates. Kernels are grouped
+    /// in order of first appearance, so I0 keeps its id.
+    pub fn compact(&self) -> Compaction {
+        let mut groups: Vec<Vec<i32>> = Vec::new();
+        let mut group_of: HashMap<i32, usize> = HashMap::new();
+        let mut by_kernel: HashMap<BTreeSet<(char, i32)>, usize> = HashMap::new();
+        for s in &self.states {
+            let g = *by_kernel.entry(s.kernel()).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[g].push(s.id);
+            group_of.insert(s.id, g);
+        }
+
+        let mut states: Vec<PilotState> = groups.iter().enumerate().map(|(g, members)| {
+            let mut candidates: Vec<Candidate> = Vec::new();
+            for id in members {
+                for c in &self.lookup_state(*id).candidates {
+                    if !candidates.contains(c) {
+                        candidates.push(*c);
+                    }
+                }
+            }
+            PilotState{id: g as i32, candidates, transitions: vec![], dropped_reductions: vec![]}
+        }).collect();
+
+        for (g, members) in groups.iter().enumerate() {
+            let mut transitions: Vec<PilotTransition> = Vec::new();
+            for id in members {
+                for t in &self.lookup_state(*id).transitions {
+                    let dest_id = group_of[&t.dest_id] as i32;
+                    let src = &self.lookup_state(*id).candidates;
+                    let dest = &self.lookup_state(t.dest_id).candidates;
+                    let candidate_map = t.candidate_map.iter().map(|(i, j)| {
+                        let i = states[g].candidates.iter().position(|c| *c == src[*i]).unwrap();
+                        let j = states[dest_id as usize].candidates.iter().position(|c| *c == dest[*j]).unwrap();
+                        (i, j)
+                    });
+                    if let Some(merged) = transitions.iter_mut().find(|m| m.character == t.character) {
+                        for pair in candidate_map {
+                            if !merged.candidate_map.contains(&pair) {
+                                merged.candidate_map.push(pair);
+                            }
+                        }
+                        merged.multiplicity = merged.multiplicity.max(t.multiplicity);
+                    } else {
+                        let candidate_map = candidate_map.collect();
+                        transitions.push(PilotTransition{character: t.character, dest_id, multiplicity: t.multiplicity, candidate_map});
+                    }
+                }
+            }
+            states[g].transitions = transitions;
+        }
+
+        let pilot = Pilot{states, axiom: self.axiom, end_marker: self.end_marker};
+        let new_conflicts = pilot.states.iter().flat_map(|s| {
+            let members = &groups[s.id as usize];
+            s.reduce_reduce_conflicts().into_iter().filter(move |confl| {
+                let c1 = s.candidates[confl.candidate_1_idx];
+                let c2 = s.candidates[confl.candidate_2_idx];
+                !members.iter().any(|id| {
+                    let orig = &self.lookup_state(*id).candidates;
+                    orig.contains(&c1) && orig.contains(&c2)
+                })
+            })
+        }).collect();
+        Compaction{pilot, groups, new_conflicts}
+    }
+}
+
+impl Compaction {
+    pub fn print_report(&self, original: &Pilot, net: &MachineNet) {
+        for (g, members) in self.groups.iter().enumerate() {
+            if members.len() > 1 {
+                let names: Vec<_> = members.iter().map(|id| format!("I{}", id)).collect();
+                eprintln!("merged {} into I{}", names.join(", "), g);
+            }
+        }
+        for c in &self.new_conflicts {
+            eprint!("new conflict: ");
+            self.pilot.print_reduce_reduce_conflict(c, net);
+        }
+        let cands = |p: &Pilot| p.states.iter().map(|s| s.candidates.len()).sum::<usize>();
+        eprintln!("states: {} -> {}, candidates: {} -> {}",
+            original.states.len(), self.pilot.states.len(), cands(original), cands(&self.pilot));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use crate::elr_pilot::*;
+
+    #[test]
+    fn test_compact_new_conflicts() {
+        let net = load_net(r"./tests/lalr_reduce_reduce.txt").unwrap();
+        let pilot = create_pilot(&net);
+        assert!(pilot.states.iter().all(|s| s.reduce_reduce_conflicts().is_empty()));
+        let compaction = pilot.compact();
+        assert!(compaction.pilot.states.len() < pilot.states.len());
+        assert_eq!(compaction.new_conflicts.len(), 2);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::analysis::NetAnalysis;
+use crate::elr_pilot::*;
+
+/// Builds the pilot without lookaheads: candidates only record the machine
+/// state, and states are identified by their base machine states.
+pub fn create_lr0_pilot(net: &MachineNet) -> Pilot {
+    build_pilot(net, &None, NO_LOOKAHEAD)
+}
+
+impl PilotState {
+    /// Final candidates clashing with an outgoing terminal edge. Without an
+    /// analysis every edge clashes (LR(0)); with one, only edges in the
+    /// follow set of the candidate machine do (SLR).
+    pub fn lr0_shift_reduce_conflicts(&self, slr: Option<&NetAnalysis>) -> Vec<ShiftReduceConflict> {
+        let mut res: Vec<ShiftReduceConflict> = Vec::new();
+        for (i, cand) in self.candidates.iter().enumerate() {
+            if !cand.is_final {
+                continue;
+            }
+            for t in self.transitions.iter().filter(|t| !t.is_nonterminal()) {
+                if slr.is_none_or(|a| a.follow(cand.machine).contains(&t.character)) {
+                    res.push(ShiftReduceConflict{state_id:self.id, candidate_idx:i, edge:t.character});
+                }
+            }
+        }
+        res
+    }
+
+    /// Pairs of final candidates. With an analysis, only pairs whose
+    /// machines have intersecting follow sets clash (SLR).
+    pub fn lr0_reduce_reduce_conflicts(&self, slr: Option<&NetAnalysis>) -> Vec<ReduceReduceConflict> {
+        let mut res: Vec<ReduceReduceConflict> = Vec::new();
+        for i in 0 .. self.candidates.len() {
+            for j in i+1 .. self.candidates.len() {
+                let ci = &self.candidates[i];
+                let cj = &self.candidates[j];
+                if !ci.is_final || !cj.is_final {
+                    continue;
+                }
+                if slr.is_none_or(|a| !a.follow(ci.machine).is_disjoint(a.follow(cj.machine))) {
+                    res.push(ReduceReduceConflict{state_id: self.id, candidate_1_idx:i, candidate_2_idx:j});
+                }
+            }
+        }
+        res
+    }
+}
+
+impl Pilot {
+    /// Prints the conflicts of a pilot built by `create_lr0_pilot`, either
+    /// as LR(0) or, when an analysis is given, as SLR.
+    pub fn print_lr0_conflicts(&self, net: &MachineNet, slr: Option<&NetAnalysis>) {
+        let mut n_confl = 0;
+        for state in &self.states {
+            let sr_confl = state.lr0_shift_reduce_conflicts(slr);
+            let rr_confl = state.lr0_reduce_reduce_conflicts(slr);
+            let c_confl = state.convergence_conflicts();
+            n_confl += self.print_conflict_lists(net, &sr_confl, &rr_confl, &c_confl, None);
+        }
+        if n_confl == 0 {
+            eprintln!("no conflicts");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use crate::analysis::NetAnalysis;
+    use super::*;
+
+    #[test]
+    fn test_lr0_and_slr_conflicts() {
+        let net = load_net(r"./tests/lalr_reduce_reduce.txt").unwrap();
+        let pilot = create_lr0_pilot(&net);
+        let analysis = NetAnalysis::new(&net);
+        let rr = |slr| pilot.states.iter().map(|s| s.lr0_reduce_reduce_conflicts(slr).len()).sum::<usize>();
+        assert_eq!(rr(None), 1);
+        assert_eq!(rr(Some(&analysis)), 1);
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        let pilot = create_lr0_pilot(&net);
+        let analysis = NetAnalysis::new(&net);
+        let sr = |slr| pilot.states.iter().map(|s| s.lr0_shift_reduce_conflicts(slr).len()).sum::<usize>();
+        assert!(sr(None) > 0);
+        assert_eq!(sr(Some(&analysis)), 0);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use crate::elr_pilot::*;
+use crate::lexer::SourceLocation;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Associativity {
+    Left,
+    Right,
+    NonAssoc
+}
+
+impl Associativity {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Associativity::Left => "%left",
+            Associativity::Right => "%right",
+            Associativity::NonAssoc => "%nonassoc"
+        }
+    }
+}
+
+/// Yacc-style precedence declarations of a net. Every `%left`, `%right` or
+/// `%nonassoc` line opens a new level, binding tighter than the ones above.
+#[derive(Debug, Clone, Default)]
+pub struct Precedence {
+    pub terminals: HashMap<char, (usize, Associativity)>,
+    /// Terminal whose precedence a machine takes, declared with `%prec`.
+    pub machines: HashMap<char, (char, SourceLocation)>,
+    levels: usize
+}
+
+impl Precedence {
+    pub fn declare(&mut self, assoc: Associativity, terminals: &[char]) {
+        self.levels += 1;
+        for t in terminals {
+            self.terminals.insert(*t, (self.levels, assoc));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terminals.is_empty()
+    }
+
+    /// The declarations in the order they were made, loosest first.
+    pub fn declarations(&self) -> Vec<(Associativity, Vec<char>)> {
+        (1..=self.levels).filter_map(|level| {
+            let mut terminals: Vec<char> = self.terminals.iter().filter(|(_, (l, _))| *l == level).map(|(t, _)| *t).collect();
+            terminals.sort();
+            let assoc = self.terminals[terminals.first()?].1;
+            Some((assoc, terminals))
+        }).collect()
+    }
+
+    pub fn terminal(&self, character: char) -> Option<(usize, Associativity)> {
+        self.terminals.get(&character).copied()
+    }
+
+    /// Precedence of reducing `machine` in final state `id`: the one given
+    /// by `%prec`, or else the one of the last terminal with a declared
+    /// precedence read on the way to `id`, when every path agrees.
+    pub fn rule(&self, net: &MachineNet, machine: char, id: i32) -> Option<(usize, Associativity)> {
+        if let Some((t, _)) = self.machines.get(&machine) {
+            return self.terminal(*t);
+        }
+        let last = self.last_terminals(net.lookup_machine(machine));
+        let mut levels = last[&id].iter().map(|t| t.and_then(|t| self.terminal(t)));
+        let first = levels.next()??;
+        if levels.all(|l| l == Some(first)) { Some(first) } else { None }
+    }
+
+    /// For every state, the last terminal with a precedence on the paths
+    /// from the initial state, `None` standing for paths without any.
+    fn last_terminals(&self, machine: &Machine) -> HashMap<i32, HashSet<Option<char>>> {
+        let mut res: HashMap<i32, HashSet<Option<char>>> = machine.states.iter().map(|s| (s.id, HashSet::new())).collect();
+        res.get_mut(&machine.initial_id()).unwrap().insert(None);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for s in &machine.states {
+                for t in &s.transitions {
+                    let new: HashSet<Option<char>> = if self.terminals.contains_key(&t.character) {
+                        HashSet::from([Some(t.character)])
+                    } else {
+                        res[&s.id].clone()
+                    };
+                    let dest = res.get_mut(&t.dest_id).unwrap();
+                    let old_len = dest.len();
+                    dest.extend(new);
+                    changed |= dest.len() != old_len;
+                }
+            }
+        }
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Resolution {
+    Shift,
+    Reduce,
+    Error
+}
+
+pub struct ResolvedConflict {
+    pub conflict: ShiftReduceConflict,
+    pub resolution: Resolution,
+    pub rule_level: usize,
+    pub edge_level: usize
+}
+
+impl ResolvedConflict {
+    pub fn describe(&self, pilot: &Pilot, net: &MachineNet) -> String {
+        let s = self.conflict.state_id;
+        let c = pilot.lookup_state(s).candidates[self.conflict.candidate_idx].describe(net);
+        let edge = self.conflict.edge;
+        let choice = match self.resolution {
+            Resolution::Shift => "shift",
+            Resolution::Reduce => "reduce",
+            Resolution::Error => "error"
+        };
+        format!("state I{s}: shift-reduce conflict between {c} and outgoing edge '{edge}' resolved as {choice} (reduction level {}, '{edge}' level {})", self.rule_level, self.edge_level)
+    }
+}
+
+impl Pilot {
+    /// Resolves the shift-reduce conflicts whose reduction and terminal
+    /// both have a precedence. Choosing the shift drops the reduction of
+    /// the candidate, choosing the reduction removes the outgoing edge, and
+    /// a nonassociative tie removes both, making the lookahead an error.
+    pub fn resolve_conflicts(&mut self, net: &MachineNet) -> Vec<ResolvedConflict> {
+        let prec = &net.precedence;
+        let mut res: Vec<ResolvedConflict> = Vec::new();
+        for state in &mut self.states {
+            let mut removed_edges: Vec<char> = Vec::new();
+            for conflict in state.shift_reduce_conflicts() {
+                let cand = &state.candidates[conflict.candidate_idx];
+                let (Some((rule_level, _)), Some((edge_level, assoc))) = (prec.rule(net, cand.machine, cand.state), prec.terminal(conflict.edge)) else {
+                    continue;
+                };
+                let resolution = if rule_level > edge_level {
+                    Resolution::Reduce
+                } else if rule_level < edge_level {
+                    Resolution::Shift
+                } else {
+                    match assoc {
+                        Associativity::Left => Resolution::Reduce,
+                        Associativity::Right => Resolution::Shift,
+                        Associativity::NonAssoc => Resolution::Error
+                    }
+                };
+                if resolution != Resolution::Shift {
+                    removed_edges.push(conflict.edge);
+                }
+                if resolution != Resolution::Reduce {
+                    state.dropped_reductions.push(conflict.candidate_idx);
+                }
+                res.push(ResolvedConflict{conflict, resolution, rule_level, edge_level});
+            }
+            state.transitions.retain(|t| !removed_edges.contains(&t.character));
+        }
+        res
+    }
+
+    pub fn print_resolutions(&self, resolved: &[ResolvedConflict], net: &MachineNet) {
+        for r in resolved {
+            eprintln!("{}", r.describe(self, net));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use crate::elr_parser::find_accepting_run;
+    use super::*;
+
+    fn count_conflicts(pilot: &Pilot) -> usize {
+        pilot.states.iter().map(|s| s.shift_reduce_conflicts().len() + s.reduce_reduce_conflicts().len()).sum()
+    }
+
+    #[test]
+    fn test_resolve_expression_conflicts() {
+        let net = load_net(r"./tests/expr_prec.txt").unwrap();
+        let mut pilot = create_pilot(&net);
+        assert!(count_conflicts(&pilot) > 0);
+        let resolved = pilot.resolve_conflicts(&net);
+        let lines: Vec<_> = resolved.iter().map(|r| r.describe(&pilot, &net)).collect();
+        assert_eq!(lines, vec![
+            "state I5: shift-reduce conflict between <(5S), p> and outgoing edge 'p' resolved as reduce (reduction level 1, 'p' level 1)",
+            "state I5: shift-reduce conflict between <(5S), t> and outgoing edge 't' resolved as shift (reduction level 1, 't' level 2)",
+            "state I6: shift-reduce conflict between <(6S), p> and outgoing edge 'p' resolved as reduce (reduction level 2, 'p' level 1)",
+            "state I6: shift-reduce conflict between <(6S), t> and outgoing edge 't' resolved as reduce (reduction level 2, 't' level 2)"
+        ]);
+        assert_eq!(count_conflicts(&pilot), 0);
+        let (tree, _) = find_accepting_run(&pilot, "npntn", 8).unwrap();
+        assert_eq!(tree.to_string(), "S(S(n) p S(S(n) t S(n)))");
+        let (tree, _) = find_accepting_run(&pilot, "npnpn", 8).unwrap();
+        assert_eq!(tree.to_string(), "S(S(S(n) p S(n)) p S(n))");
+    }
+
+    #[test]
+    fn test_resolve_dangling_else() {
+        let net = load_net(r"./tests/dangling_else_prec.txt").unwrap();
+        let mut pilot = create_pilot(&net);
+        let resolved = pilot.resolve_conflicts(&net);
+        let lines: Vec<_> = resolved.iter().map(|r| r.describe(&pilot, &net)).collect();
+        assert_eq!(lines, vec!["state I8: shift-reduce conflict between <(0B), b> and outgoing edge 'b' resolved as shift (reduction level 1, 'b' level 2)"]);
+        assert_eq!(count_conflicts(&pilot), 0);
+    }
+}
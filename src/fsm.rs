@@ -1,7 +1,16 @@
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::HashSet;
+use std::fmt;
+use crate::lexer::SourceLocation;
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Transition {
     pub character: char,
-    pub dest_id: i32
+    pub dest_id: i32,
+    pub location: SourceLocation
 }
 
 impl Transition {
@@ -10,28 +19,34 @@ impl Transition {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct State {
     pub id: i32,
+    /// The name given in the net file; numeric names are also the id.
+    pub name: String,
     pub transitions: Vec<Transition>,
     pub is_initial: bool,
-    pub is_final: bool
+    pub is_final: bool,
+    pub location: SourceLocation
+}
+
+impl State {
+    pub fn try_lookup_transition(&self, character: char) -> Option<&Transition> {
+        self.transitions.iter().find(|t| t.character == character)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Machine {
     pub name: char,
-    pub states: Vec<State>
+    pub states: Vec<State>,
+    /// Where the machine is defined, possibly in another file.
+    pub location: SourceLocation
 }
 
 impl Machine {
     pub fn try_lookup_state(&self, id: i32) -> Option<&State> {
-        for s in &self.states {
-            if s.id == id {
-                return Some(s);
-            }
-        }
-        None
+        self.states.iter().find(|s| s.id == id)
     }
 
     pub fn lookup_state(&self, id: i32) -> &State {
@@ -41,4 +56,208 @@ impl Machine {
             panic!("state {id} does not exist")
         }
     }
+
+    pub fn try_lookup_state_by_name(&self, name: &str) -> Option<&State> {
+        self.states.iter().find(|s| s.name == name)
+    }
+
+    /// The initial state; a valid machine has exactly one.
+    pub fn initial_state(&self) -> &State {
+        if let Some(s) = self.states.iter().find(|s| s.is_initial) {
+            s
+        } else {
+            panic!("machine {} has no initial state", self.name)
+        }
+    }
+
+    pub fn initial_id(&self) -> i32 {
+        self.initial_state().id
+    }
+
+    /// The name of state `id`, or the id itself for a missing state.
+    pub fn state_name(&self, id: i32) -> String {
+        self.try_lookup_state(id).map_or_else(|| id.to_string(), |s| s.name.clone())
+    }
+
+    /// A state id above every id and numeric name in use, so that it can
+    /// also serve as the name of a new state.
+    pub fn fresh_id(&self) -> i32 {
+        self.states.iter().flat_map(|s| [Some(s.id), s.name.parse().ok()]).flatten().max().map_or(0, |id| id + 1)
+    }
+}
+
+impl Machine {
+    fn initial_ids(&self) -> BTreeSet<i32> {
+        self.states.iter().filter(|s| s.is_initial).map(|s| s.id).collect()
+    }
+
+    /// Whether the machine, read as an automaton over terminals that may be
+    /// nondeterministic, accepts `word`.
+    pub fn accepts(&self, word: &str) -> bool {
+        let mut current = self.initial_ids();
+        for c in word.chars() {
+            current = current.iter().flat_map(|id| {
+                self.lookup_state(*id).transitions.iter().filter(|t| t.character == c).map(|t| t.dest_id)
+            }).collect();
+        }
+        current.iter().any(|id| self.lookup_state(*id).is_final)
+    }
+
+    /// Whether no state has two arcs with the same label.
+    pub fn is_deterministic(&self) -> bool {
+        self.states.iter().all(|s| {
+            s.transitions.iter().enumerate().all(|(i, t)| s.transitions[i+1..].iter().all(|x| x.character != t.character))
+        })
+    }
+
+    /// The equivalent deterministic machine by the subset construction.
+    /// Only subsets reachable from the initial states become states, and
+    /// the empty subset is left out, so the result may be partial.
+    pub fn determinize(&self) -> Machine {
+        let mut subsets: Vec<BTreeSet<i32>> = vec![self.initial_ids()];
+        let mut index: HashMap<BTreeSet<i32>, i32> = HashMap::from([(subsets[0].clone(), 0)]);
+        let mut states: Vec<State> = Vec::new();
+        let mut i = 0;
+        while i < subsets.len() {
+            let subset = subsets[i].clone();
+            let mut moves: BTreeMap<char, BTreeSet<i32>> = BTreeMap::new();
+            for id in &subset {
+                for t in &self.lookup_state(*id).transitions {
+                    moves.entry(t.character).or_default().insert(t.dest_id);
+                }
+            }
+            let mut transitions: Vec<Transition> = Vec::new();
+            for (character, dest) in moves {
+                let dest_id = match index.get(&dest) {
+                    Some(id) => *id,
+                    None => {
+                        index.insert(dest.clone(), subsets.len() as i32);
+                        subsets.push(dest);
+                        subsets.len() as i32 - 1
+                    }
+                };
+                transitions.push(Transition{character, dest_id, location: SourceLocation::default()});
+            }
+            states.push(State{
+                id: i as i32,
+                name: i.to_string(),
+                transitions,
+                is_initial: i == 0,
+                is_final: subset.iter().any(|id| self.lookup_state(*id).is_final),
+                location: SourceLocation::default()
+            });
+            i += 1;
+        }
+        Machine{name: self.name, states, location: self.location.clone()}
+    }
+
+    /// States reachable from the initial state.
+    pub fn reachable(&self) -> HashSet<i32> {
+        let mut res: HashSet<i32> = self.initial_ids().into_iter().collect();
+        let mut work: Vec<i32> = res.iter().copied().collect();
+        while let Some(id) = work.pop() {
+            for t in &self.lookup_state(id).transitions {
+                if res.insert(t.dest_id) {
+                    work.push(t.dest_id);
+                }
+            }
+        }
+        res
+    }
+
+    /// States from which a final state can be reached.
+    pub fn coreachable(&self) -> HashSet<i32> {
+        let mut res: HashSet<i32> = self.states.iter().filter(|s| s.is_final).map(|s| s.id).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for s in &self.states {
+                if !res.contains(&s.id) && s.transitions.iter().any(|t| res.contains(&t.dest_id)) {
+                    res.insert(s.id);
+                    changed = true;
+                }
+            }
+        }
+        res
+    }
+
+    /// The minimal deterministic machine accepting the same strings. States
+    /// that cannot reach a final state are dropped, the others are merged
+    /// by partition refinement and numbered in breadth-first order.
+    pub fn minimize(&self) -> Machine {
+        let dfa = self.determinize();
+        let live = dfa.coreachable();
+        let arcs: Vec<Vec<(char, usize)>> = dfa.states.iter().map(|s| {
+            s.transitions.iter().filter(|t| live.contains(&t.dest_id)).map(|t| (t.character, t.dest_id as usize)).collect()
+        }).collect();
+        let mut class: Vec<usize> = dfa.states.iter().map(|s| s.is_final as usize).collect();
+        let mut count = class.iter().collect::<HashSet<_>>().len();
+        loop {
+            let mut signatures: HashMap<(usize, Vec<(char, usize)>), usize> = HashMap::new();
+            let next: Vec<usize> = arcs.iter().enumerate().map(|(i, a)| {
+                let signature = (class[i], a.iter().map(|(c, dest)| (*c, class[*dest])).collect());
+                let fresh = signatures.len();
+                *signatures.entry(signature).or_insert(fresh)
+            }).collect();
+            class = next;
+            if signatures.len() == count {
+                break;
+            }
+            count = signatures.len();
+        }
+
+        // Number the classes in breadth-first order from the initial one.
+        let mut number: HashMap<usize, i32> = HashMap::from([(class[0], 0)]);
+        let mut order: Vec<usize> = vec![0];
+        let mut i = 0;
+        while i < order.len() {
+            for (_, dest) in &arcs[order[i]] {
+                if let Entry::Vacant(e) = number.entry(class[*dest]) {
+                    e.insert(order.len() as i32);
+                    order.push(*dest);
+                }
+            }
+            i += 1;
+        }
+        let states = order.iter().enumerate().map(|(id, rep)| State{
+            id: id as i32,
+            name: id.to_string(),
+            transitions: arcs[*rep].iter().map(|(c, dest)| {
+                Transition{character: *c, dest_id: number[&class[*dest]], location: SourceLocation::default()}
+            }).collect(),
+            is_initial: id == 0,
+            is_final: dfa.states[*rep].is_final,
+            location: SourceLocation::default()
+        }).collect();
+        Machine{name: self.name, states, location: self.location.clone()}
+    }
+}
+
+impl Machine {
+    /// Writes the machine in the syntax of machine net files, every line
+    /// prefixed by `indent`, with `header` between the name and the body.
+    pub fn write_mnet(&self, f: &mut fmt::Formatter<'_>, indent: &str, header: &str) -> fmt::Result {
+        writeln!(f, "{}machine {}{} {{", indent, self.name, header)?;
+        for s in &self.states {
+            writeln!(f, "{}    state {} {{", indent, s.name)?;
+            if s.is_initial {
+                writeln!(f, "{}        initial;", indent)?;
+            }
+            if s.is_final {
+                writeln!(f, "{}        final;", indent)?;
+            }
+            for t in &s.transitions {
+                writeln!(f, "{}        {} -> {};", indent, t.character, self.state_name(t.dest_id))?;
+            }
+            writeln!(f, "{}    }}", indent)?;
+        }
+        writeln!(f, "{}}}", indent)
+    }
+}
+
+/// Prints the machine in the syntax of machine net files.
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_mnet(f, "", "")
+    }
 }
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use crate::elr_parser::Action;
+use crate::elr_pilot::*;
+use crate::syntax_tree::SyntaxTree;
+
+/// Node of a shared packed parse forest: a terminal or a machine spanning
+/// `input[start..end]`, with one family of children per way of deriving it.
+#[derive(Debug, Clone)]
+pub struct ForestNode {
+    pub symbol: char,
+    pub start: usize,
+    pub end: usize,
+    pub families: Vec<Vec<usize>>
+}
+
+#[derive(Debug, Default)]
+pub struct Forest {
+    pub nodes: Vec<ForestNode>,
+    index: HashMap<(char, usize, usize), usize>
+}
+
+impl Forest {
+    fn node(&mut self, symbol: char, start: usize, end: usize) -> usize {
+        *self.index.entry((symbol, start, end)).or_insert_with(|| {
+            self.nodes.push(ForestNode{symbol, start, end, families: Vec::new()});
+            self.nodes.len() - 1
+        })
+    }
+
+    fn add_family(&mut self, id: usize, children: Vec<usize>) -> bool {
+        let families = &mut self.nodes[id].families;
+        if families.contains(&children) {
+            false
+        } else {
+            families.push(children);
+            true
+        }
+    }
+
+    /// Unpacks up to `limit` trees rooted in node `id`. Families looping
+    /// back to a node being unpacked are skipped, so cyclic nets yield
+    /// only their acyclic trees.
+    pub fn trees(&self, id: usize, limit: usize) -> Vec<SyntaxTree> {
+        self.unpack(id, limit, &mut HashSet::new())
+    }
+
+    fn unpack(&self, id: usize, limit: usize, active: &mut HashSet<usize>) -> Vec<SyntaxTree> {
+        let node = &self.nodes[id];
+        if !node.symbol.is_ascii_uppercase() {
+            return vec![SyntaxTree::Leaf(node.symbol)];
+        }
+        if !active.insert(id) {
+            return vec![];
+        }
+        let mut res: Vec<SyntaxTree> = Vec::new();
+        for family in &node.families {
+            let mut partial: Vec<Vec<SyntaxTree>> = vec![vec![]];
+            for child in family {
+                let subtrees = self.unpack(*child, limit, active);
+                partial = partial.iter().flat_map(|p| {
+                    subtrees.iter().map(move |t| {
+                        let mut p = p.clone();
+                        p.push(t.clone());
+                        p
+                    })
+                }).take(limit).collect();
+            }
+            res.extend(partial.into_iter().map(|children| SyntaxTree::Node(node.symbol, children)));
+            if res.len() >= limit {
+                res.truncate(limit);
+                break;
+            }
+        }
+        active.remove(&id);
+        res
+    }
+
+    fn label(&self, id: usize) -> String {
+        let n = &self.nodes[id];
+        format!("{}[{},{}]", n.symbol, n.start, n.end)
+    }
+}
+
+impl fmt::Display for Forest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, n) in self.nodes.iter().enumerate().filter(|(_, n)| n.symbol.is_ascii_uppercase()) {
+            let families: Vec<_> = n.families.iter().map(|fam| {
+                if fam.is_empty() {
+                    "ε".to_string()
+                } else {
+                    fam.iter().map(|c| self.label(*c)).collect::<Vec<_>>().join(" ")
+                }
+            }).collect();
+            writeln!(f, "{} -> {}", self.label(id), families.join(" | "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A point where the parser had more than one action available.
+#[derive(Debug, Clone)]
+pub struct Fork {
+    pub position: usize,
+    pub state: i32,
+    pub actions: Vec<Action>
+}
+
+impl Fork {
+    pub fn describe(&self, pilot: &Pilot, net: &MachineNet) -> String {
+        let actions: Vec<_> = self.actions.iter().map(|a| match a {
+            Action::Shift(c) => format!("shift '{}'", c),
+            Action::Reduce(i) | Action::Accept(i) => {
+                format!("reduce {}", pilot.lookup_state(self.state).candidates[*i].describe(net))
+            }
+        }).collect();
+        format!("fork at {} in I{}: {}", self.position, self.state, actions.join(", "))
+    }
+}
+
+/// Outcome of a generalized parse: the forest of every partial parse, the
+/// forest node of the whole sentence if it was accepted, and the forks.
+#[derive(Debug)]
+pub struct GlrParse {
+    pub forest: Forest,
+    pub root: Option<usize>,
+    pub forks: Vec<Fork>,
+    /// Position of the first terminal no stack could shift, or the length
+    /// of the input when no stack accepts at the end marker.
+    pub error_position: Option<usize>
+}
+
+impl GlrParse {
+    pub fn trees(&self, limit: usize) -> Vec<SyntaxTree> {
+        match self.root {
+            Some(root) => self.forest.trees(root, limit),
+            None => vec![]
+        }
+    }
+
+    pub fn print_forks(&self, pilot: &Pilot, net: &MachineNet) {
+        for fork in &self.forks {
+            eprintln!("{}", fork.describe(pilot, net));
+        }
+    }
+}
+
+/// Node of the graph-structured stack. Each edge leads to a node below,
+/// is labelled by the symbol shifted on top of it, and carries the forest
+/// node read by that shift.
+struct StackNode {
+    state: i32,
+    position: usize,
+    edges: Vec<(usize, char, usize)>
+}
+
+struct Glr<'a> {
+    pilot: &'a Pilot,
+    input: Vec<char>,
+    nodes: Vec<StackNode>,
+    forest: Forest,
+    root: Option<usize>
+}
+
+impl Glr<'_> {
+    fn stack_node(&mut self, state: i32, position: usize) -> (usize, bool) {
+        if let Some(i) = self.nodes.iter().position(|n| n.state == state && n.position == position) {
+            return (i, false);
+        }
+        self.nodes.push(StackNode{state, position, edges: Vec::new()});
+        (self.nodes.len() - 1, true)
+    }
+
+    fn add_edge(&mut self, node: usize, edge: (usize, char, usize)) -> bool {
+        if self.nodes[node].edges.contains(&edge) {
+            false
+        } else {
+            self.nodes[node].edges.push(edge);
+            true
+        }
+    }
+
+    /// Walks back from candidate `idx` of `node` to every node where its
+    /// machine was entered, collecting the forest nodes read on the way.
+    /// Paths longer than the stack has nodes only repeat nullable loops
+    /// and are cut.
+    fn origins(&self, node: usize, idx: usize, depth: usize, children: &mut Vec<usize>, res: &mut Vec<(usize, usize, Vec<usize>)>) {
+        let state = self.pilot.lookup_state(self.nodes[node].state);
+        let mut entered_here = self.nodes[node].edges.is_empty();
+        for (below, character, tree) in &self.nodes[node].edges {
+            let below_state = self.pilot.lookup_state(self.nodes[*below].state);
+            let trans = below_state.transitions.iter().find(|t| t.character == *character).unwrap();
+            debug_assert_eq!(trans.dest_id, state.id);
+            let sources: Vec<_> = trans.candidate_map.iter().filter(|(_, d)| *d == idx).map(|(s, _)| *s).collect();
+            if sources.is_empty() {
+                entered_here = true;
+                continue;
+            }
+            if depth > self.nodes.len() {
+                continue;
+            }
+            for src in sources {
+                children.push(*tree);
+                self.origins(*below, src, depth + 1, children, res);
+                children.pop();
+            }
+        }
+        if entered_here {
+            res.push((node, idx, children.iter().rev().copied().collect()));
+        }
+    }
+
+    /// Performs every reduction possible at `position` until nothing new
+    /// appears, returning the actions allowed in each stack node.
+    fn reduce_all(&mut self, position: usize) -> HashMap<usize, Vec<Action>> {
+        let lookahead = self.input.get(position).copied().unwrap_or(self.pilot.end_marker);
+        let mut actions: HashMap<usize, Vec<Action>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let current: Vec<usize> = (0..self.nodes.len()).filter(|i| self.nodes[*i].position == position).collect();
+            for node in current {
+                let pilot = self.pilot;
+                let state = pilot.lookup_state(self.nodes[node].state);
+                let mut node_actions: Vec<Action> = Vec::new();
+                if state.transitions.iter().any(|t| t.character == lookahead) {
+                    node_actions.push(Action::Shift(lookahead));
+                }
+                for (i, c) in state.candidates.iter().enumerate() {
+                    if !state.reduces(i) || c.lookahead != lookahead {
+                        continue;
+                    }
+                    node_actions.push(Action::Reduce(i));
+                    let mut origins = Vec::new();
+                    self.origins(node, i, 0, &mut Vec::new(), &mut origins);
+                    for (origin, idx, children) in origins {
+                        let start = self.nodes[origin].position;
+                        let tree = self.forest.node(c.machine, start, position);
+                        changed |= self.forest.add_family(tree, children);
+                        if origin == 0 && idx == 0 && c.machine == pilot.axiom && lookahead == pilot.end_marker {
+                            self.root = Some(tree);
+                            continue;
+                        }
+                        let goto = pilot.lookup_state(self.nodes[origin].state).transitions.iter()
+                            .find(|t| t.character == c.machine).unwrap().dest_id;
+                        let (dest, created) = self.stack_node(goto, position);
+                        changed |= created;
+                        changed |= self.add_edge(dest, (origin, c.machine, tree));
+                    }
+                }
+                actions.insert(node, node_actions);
+            }
+        }
+        actions
+    }
+
+    fn shift_all(&mut self, position: usize) -> bool {
+        let character = self.input[position];
+        let leaf = self.forest.node(character, position, position + 1);
+        let current: Vec<usize> = (0..self.nodes.len()).filter(|i| self.nodes[*i].position == position).collect();
+        let mut shifted = false;
+        for node in current {
+            let state = self.pilot.lookup_state(self.nodes[node].state);
+            if let Some(t) = state.transitions.iter().find(|t| t.character == character) {
+                let (dest, _) = self.stack_node(t.dest_id, position + 1);
+                self.add_edge(dest, (node, character, leaf));
+                shifted = true;
+            }
+        }
+        shifted
+    }
+}
+
+/// Parses `input` with a graph-structured stack, following every action
+/// the pilot allows, so that conflicts fork the parse instead of stopping
+/// it. Stacks reaching the same pilot state at the same position are
+/// merged, and equal subtrees are shared in a packed forest.
+pub fn glr_parse(pilot: &Pilot, input: &str) -> GlrParse {
+    let mut glr = Glr{
+        pilot,
+        input: input.chars().collect(),
+        nodes: vec![StackNode{state: 0, position: 0, edges: Vec::new()}],
+        forest: Forest::default(),
+        root: None
+    };
+    let mut forks: Vec<Fork> = Vec::new();
+    let mut error_position = None;
+    for position in 0..=glr.input.len() {
+        let mut actions: Vec<_> = glr.reduce_all(position).into_iter().collect();
+        actions.sort_by_key(|(node, _)| glr.nodes[*node].state);
+        for (node, actions) in actions {
+            if actions.len() > 1 {
+                forks.push(Fork{position, state: glr.nodes[node].state, actions});
+            }
+        }
+        if position < glr.input.len() && !glr.shift_all(position) {
+            error_position = Some(position);
+            break;
+        }
+    }
+    if glr.root.is_none() && error_position.is_none() {
+        error_position = Some(glr.input.len());
+    }
+    GlrParse{forest: glr.forest, root: glr.root, forks, error_position}
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use crate::ambiguity::syntax_trees;
+    use super::*;
+
+    #[test]
+    fn test_glr_parse_ambiguous() {
+        let net = load_net(r"./tests/dangling_else.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let parse = glr_parse(&pilot, "aacbc");
+        let forks: Vec<_> = parse.forks.iter().map(|f| f.describe(&pilot, &net)).collect();
+        assert_eq!(forks, vec!["fork at 3 in I8: shift 'b', reduce <(0B), b>"]);
+        let mut trees = parse.trees(10);
+        let mut expected = syntax_trees(&net, "aacbc", 10);
+        trees.sort_by_key(|t| t.to_string());
+        expected.sort_by_key(|t| t.to_string());
+        assert_eq!(trees, expected);
+        let trees: Vec<_> = trees.iter().map(|t| t.to_string()).collect();
+        assert_eq!(trees, vec!["S(a A(S(a A(c) B())) B(b A(c)))", "S(a A(S(a A(c) B(b A(c)))) B())"]);
+        let parse = glr_parse(&pilot, "aab");
+        assert!(parse.root.is_none());
+        assert_eq!(parse.error_position, Some(2));
+        // Rejected at the end marker, past the last terminal of the input
+        assert_eq!(glr_parse(&pilot, "aa").error_position, Some(2));
+    }
+
+    #[test]
+    fn test_glr_parse_deterministic() {
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        let pilot = create_pilot(&net);
+        let parse = glr_parse(&pilot, "dcaabb");
+        assert!(parse.forks.is_empty());
+        let trees: Vec<_> = parse.trees(10).iter().map(|t| t.to_string()).collect();
+        assert_eq!(trees, vec!["S(d B(c A(a a B(b)) b))"]);
+        assert!(glr_parse(&pilot, "dcab").root.is_none());
+        assert_eq!(glr_parse(&pilot, "dd").error_position, Some(1));
+    }
+}
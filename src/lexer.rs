@@ -1,8 +1,11 @@
 use std::fs;
-use std::io::prelude::*;
-use std::{fs::File, path::Path};
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 pub struct Lexer {
+    path: PathBuf,
     input: String,
     read_idx: usize,
     read_loc: SourceLocation,
@@ -18,7 +21,13 @@ pub enum TokenValue {
     Invalid,
     Number(i32),
     Ident(char),
+    /// Identifier longer than one character, only valid as a state name.
+    Name(String),
+    Char(char),
+    /// Double-quoted file path.
+    Str(String),
     Semi,
+    Comma,
     LBrace,
     RBrace,
     RArrow,
@@ -27,21 +36,52 @@ pub enum TokenValue {
     KwState,
     KwInitial,
     KwFinal,
+    KwInclude,
+    KwImport,
+    KwAs,
+    KwFrom,
+    KwLeft,
+    KwRight,
+    KwNonAssoc,
+    KwPrec,
+    KwStart,
+    KwEnd,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Default, Eq, Hash, PartialEq)]
 pub struct SourceLocation {
+    /// Path of the file, shared by all the locations read from it, `None`
+    /// when the location does not come from a file.
+    pub file: Option<Arc<str>>,
     pub row: usize,
     pub col: usize,
 }
 
 impl SourceLocation {
-    fn new() -> SourceLocation {
-        SourceLocation { row: 0, col: 0 }
+    fn new(file: &Path) -> SourceLocation {
+        SourceLocation { file: Some(file.display().to_string().into()), row: 0, col: 0 }
+    }
+
+    /// The location as printed in diagnostics, with the file path when
+    /// there is one.
+    fn prefix(&self) -> String {
+        let position = format!("{}:{}", self.row + 1, self.col + 1);
+        match &self.file {
+            None => position,
+            Some(file) => format!("{}:{}", file, position),
+        }
     }
 
     pub fn emit_error(&self, s: &str) {
-        eprintln!("{}:{}: error: {}", self.row + 1, self.col + 1, s);
+        eprintln!("{}: error: {}", self.prefix(), s);
+    }
+
+    pub fn emit_warning(&self, s: &str) {
+        eprintln!("{}: warning: {}", self.prefix(), s);
+    }
+
+    pub fn emit_note(&self, s: &str) {
+        eprintln!("{}: note: {}", self.prefix(), s);
     }
 }
 
@@ -54,7 +94,7 @@ pub struct Token {
 impl Token {
     fn from_frag(frag: &Fragment, value: TokenValue) -> Token {
         Token {
-            location: frag.loc,
+            location: frag.loc.clone(),
             value,
         }
     }
@@ -62,18 +102,32 @@ impl Token {
 
 impl Lexer {
     pub fn from_path(path: &Path) -> Lexer {
-        match fs::read_to_string(path) {
-            Ok(s) => Lexer {
-                input: s,
-                read_idx: 0,
-                read_loc: SourceLocation::new(),
-            },
+        match Lexer::try_from_path(path) {
+            Ok(lexer) => lexer,
             Err(why) => panic!("Error file: {}", why),
         }
     }
 
-    fn advance(&mut self, len: usize) -> Fragment {
-        let loc = self.read_loc;
+    pub fn try_from_path(path: &Path) -> io::Result<Lexer> {
+        let input = fs::read_to_string(path)?;
+        Ok(Lexer {
+            path: path.to_path_buf(),
+            input,
+            read_idx: 0,
+            read_loc: SourceLocation::new(path),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn location(&self) -> SourceLocation {
+        self.read_loc.clone()
+    }
+
+    fn advance(&mut self, len: usize) -> Fragment<'_> {
+        let loc = self.read_loc.clone();
         let slice = &self.input[self.read_idx..];
         let mut iter = slice.char_indices();
         let end = loop {
@@ -96,7 +150,7 @@ impl Lexer {
         Fragment { loc, val }
     }
 
-    fn accept_pattern(&mut self, pat: &str) -> Option<Fragment> {
+    fn accept_pattern(&mut self, pat: &str) -> Option<Fragment<'_>> {
         let next = &self.input[self.read_idx..];
         if next.starts_with(pat) {
             Some(self.advance(pat.len()))
@@ -105,7 +159,7 @@ impl Lexer {
         }
     }
 
-    fn accept_identifier(&mut self) -> Option<Fragment> {
+    fn accept_identifier(&mut self) -> Option<Fragment<'_>> {
         let slice = &self.input[self.read_idx..];
         let mut next_iter = slice.char_indices();
         let end = loop {
@@ -129,7 +183,28 @@ impl Lexer {
         }
     }
 
-    fn accept_number(&mut self) -> Option<Fragment> {
+    fn accept_char_literal(&mut self) -> Option<(Fragment<'_>, char)> {
+        let slice = &self.input[self.read_idx..];
+        let mut chars = slice.char_indices();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some((_, '\'')), Some((_, c)), Some((i, '\''))) => Some((self.advance(i + 1), c)),
+            _ => None
+        }
+    }
+
+    fn accept_string_literal(&mut self) -> Option<(Fragment<'_>, String)> {
+        let slice = &self.input[self.read_idx..];
+        let body = slice.strip_prefix('"')?;
+        match body.find(['"', '\n']) {
+            Some(i) if body[i..].starts_with('"') => {
+                let value = body[..i].to_string();
+                Some((self.advance(i + 2), value))
+            }
+            _ => None
+        }
+    }
+
+    fn accept_number(&mut self) -> Option<Fragment<'_>> {
         let slice = &self.input[self.read_idx..];
         let mut next_iter = slice.char_indices();
         let end = loop {
@@ -148,7 +223,7 @@ impl Lexer {
         }
     }
 
-    fn accept_invalid(&mut self) -> Option<Fragment> {
+    fn accept_invalid(&mut self) -> Option<Fragment<'_>> {
         let slice = &self.input[self.read_idx..];
         if let Some((i, _)) = slice.char_indices().next() {
             Some(self.advance(i))
@@ -171,21 +246,74 @@ impl Lexer {
         };
         self.advance(end);
     }
+
+    /// Skips a `//` comment up to the end of the line, or a `/* */`
+    /// comment, which does not nest. Returns whether there was one; a
+    /// comment that is never closed is left to `accept_unterminated_comment`.
+    fn skip_comment(&mut self) -> bool {
+        let slice = &self.input[self.read_idx..];
+        if slice.starts_with("//") {
+            let end = slice.find('\n').unwrap_or(slice.len());
+            self.advance(end);
+        } else if let Some(end) = slice.strip_prefix("/*").and_then(|body| body.find("*/")) {
+            self.advance(end + 4);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn accept_unterminated_comment(&mut self) -> Option<Fragment<'_>> {
+        let len = self.input.len() - self.read_idx;
+        if self.input[self.read_idx..].starts_with("/*") {
+            Some(self.advance(len))
+        } else {
+            None
+        }
+    }
+
+    fn skip_trivia(&mut self) {
+        self.skip_whitespace();
+        while self.skip_comment() {
+            self.skip_whitespace();
+        }
+    }
 }
 
 impl Iterator for Lexer {
     type Item = Token;
 
     fn next(&mut self) -> Option<Token> {
-        self.skip_whitespace();
-        if let Some(frag) = self.accept_pattern(";") {
+        self.skip_trivia();
+        if let Some(frag) = self.accept_unterminated_comment() {
+            frag.loc.emit_error("unterminated comment");
+            return Some(Token::from_frag(&frag, TokenValue::Invalid));
+        } else if let Some(frag) = self.accept_pattern(";") {
             return Some(Token::from_frag(&frag, TokenValue::Semi));
+        } else if let Some(frag) = self.accept_pattern(",") {
+            return Some(Token::from_frag(&frag, TokenValue::Comma));
         } else if let Some(frag) = self.accept_pattern("{") {
             return Some(Token::from_frag(&frag, TokenValue::LBrace));
         } else if let Some(frag) = self.accept_pattern("}") {
             return Some(Token::from_frag(&frag, TokenValue::RBrace));
         } else if let Some(frag) = self.accept_pattern("->") {
             return Some(Token::from_frag(&frag, TokenValue::RArrow));
+        } else if let Some(frag) = self.accept_pattern("%left") {
+            return Some(Token::from_frag(&frag, TokenValue::KwLeft));
+        } else if let Some(frag) = self.accept_pattern("%right") {
+            return Some(Token::from_frag(&frag, TokenValue::KwRight));
+        } else if let Some(frag) = self.accept_pattern("%nonassoc") {
+            return Some(Token::from_frag(&frag, TokenValue::KwNonAssoc));
+        } else if let Some(frag) = self.accept_pattern("%prec") {
+            return Some(Token::from_frag(&frag, TokenValue::KwPrec));
+        } else if let Some(frag) = self.accept_pattern("%start") {
+            return Some(Token::from_frag(&frag, TokenValue::KwStart));
+        } else if let Some(frag) = self.accept_pattern("%end") {
+            return Some(Token::from_frag(&frag, TokenValue::KwEnd));
+        } else if let Some((frag, c)) = self.accept_char_literal() {
+            return Some(Token::from_frag(&frag, TokenValue::Char(c)));
+        } else if let Some((frag, path)) = self.accept_string_literal() {
+            return Some(Token::from_frag(&frag, TokenValue::Str(path)));
         } else if let Some(frag) = self.accept_identifier() {
             let id = frag.val;
             if id == "mnet" {
@@ -198,14 +326,21 @@ impl Iterator for Lexer {
                 return Some(Token::from_frag(&frag, TokenValue::KwInitial));
             } else if id == "final" {
                 return Some(Token::from_frag(&frag, TokenValue::KwFinal));
+            } else if id == "include" {
+                return Some(Token::from_frag(&frag, TokenValue::KwInclude));
+            } else if id == "import" {
+                return Some(Token::from_frag(&frag, TokenValue::KwImport));
+            } else if id == "as" {
+                return Some(Token::from_frag(&frag, TokenValue::KwAs));
+            } else if id == "from" {
+                return Some(Token::from_frag(&frag, TokenValue::KwFrom));
             } else if id.len() == 1 {
                 return Some(Token::from_frag(
                     &frag,
                     TokenValue::Ident(id.chars().next().unwrap()),
                 ));
             } else {
-                frag.loc.emit_error("identifier longer than one character");
-                return Some(Token::from_frag(&frag, TokenValue::Invalid));
+                return Some(Token::from_frag(&frag, TokenValue::Name(id.to_string())));
             }
         } else if let Some(frag) = self.accept_number() {
             let num = frag.val.parse().unwrap();
@@ -213,6 +348,6 @@ impl Iterator for Lexer {
         } else if let Some(frag) = self.accept_invalid() {
             return Some(Token::from_frag(&frag, TokenValue::Invalid));
         }
-        return None;
+        None
     }
 }
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use crate::elr_pilot::*;
+use crate::lexer::SourceLocation;
+use crate::regular::called_machines;
+
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum LintCode {
+    UnreachableState,
+    DeadState,
+    UnusedMachine,
+    UnproductiveMachine,
+    NonFinalSink,
+    SingleUseTerminal
+}
+
+impl LintCode {
+    pub const ALL: [LintCode; 6] = [
+        LintCode::UnreachableState,
+        LintCode::DeadState,
+        LintCode::UnusedMachine,
+        LintCode::UnproductiveMachine,
+        LintCode::NonFinalSink,
+        LintCode::SingleUseTerminal
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintCode::UnreachableState => "unreachable-state",
+            LintCode::DeadState => "dead-state",
+            LintCode::UnusedMachine => "unused-machine",
+            LintCode::UnproductiveMachine => "unproductive-machine",
+            LintCode::NonFinalSink => "non-final-sink",
+            LintCode::SingleUseTerminal => "single-use-terminal"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LintCode> {
+        LintCode::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny
+}
+
+/// The level of every lint, `Warn` unless set otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<LintCode, LintLevel>
+}
+
+impl LintConfig {
+    pub fn set(&mut self, code: LintCode, level: LintLevel) {
+        self.levels.insert(code, level);
+    }
+
+    pub fn level(&self, code: LintCode) -> LintLevel {
+        self.levels.get(&code).copied().unwrap_or(LintLevel::Warn)
+    }
+}
+
+/// Something legal but suspicious in a net.
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub code: LintCode,
+    pub message: String,
+    pub location: SourceLocation
+}
+
+impl Lint {
+    pub fn print(&self, level: LintLevel) {
+        let message = format!("{} [{}]", self.message, self.code.name());
+        match level {
+            LintLevel::Allow => (),
+            LintLevel::Warn => self.location.emit_warning(&message),
+            LintLevel::Deny => self.location.emit_error(&message)
+        }
+    }
+}
+
+/// Machines generating at least one terminal string.
+fn productive_machines(net: &MachineNet) -> HashSet<char> {
+    let mut res: HashSet<char> = HashSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            if res.contains(&m.name) {
+                continue;
+            }
+            let mut live: HashSet<i32> = m.states.iter().filter(|s| s.is_final).map(|s| s.id).collect();
+            let mut grown = true;
+            while grown {
+                grown = false;
+                for s in &m.states {
+                    if !live.contains(&s.id) && s.transitions.iter().any(|t| live.contains(&t.dest_id) && (!t.is_nonterminal() || res.contains(&t.character))) {
+                        live.insert(s.id);
+                        grown = true;
+                    }
+                }
+            }
+            if live.contains(&m.initial_id()) {
+                res.insert(m.name);
+                changed = true;
+            }
+        }
+    }
+    res
+}
+
+fn state_lints(m: &Machine, res: &mut Vec<Lint>) {
+    let reachable = m.reachable();
+    let coreachable = m.coreachable();
+    for s in &m.states {
+        let (code, message) = if !reachable.contains(&s.id) {
+            (LintCode::UnreachableState, format!("state {}{} is unreachable from state {}{}", s.name, m.name, m.initial_state().name, m.name))
+        } else if s.transitions.is_empty() && !s.is_final {
+            (LintCode::NonFinalSink, format!("state {}{} has no outgoing arcs but is not final", s.name, m.name))
+        } else if !coreachable.contains(&s.id) {
+            (LintCode::DeadState, format!("no final state of {} can be reached from state {}{}", m.name, s.name, m.name))
+        } else {
+            continue;
+        };
+        res.push(Lint{code, message, location: s.location.clone()});
+    }
+}
+
+/// Runs every lint on a valid net. A sink state that is not final is
+/// reported as such rather than as a dead state.
+pub fn lint(net: &MachineNet) -> Vec<Lint> {
+    let mut res: Vec<Lint> = Vec::new();
+    for m in &net.machines {
+        state_lints(m, &mut res);
+    }
+
+    let called = called_machines(net);
+    let productive = productive_machines(net);
+    for m in &net.machines {
+        let location = &m.location;
+        if !called.contains(&m.name) {
+            let message = format!("machine {} is never called from {}", m.name, net.axiom);
+            res.push(Lint{code: LintCode::UnusedMachine, message, location: location.clone()});
+        }
+        if !productive.contains(&m.name) {
+            let message = format!("machine {} generates no terminal string", m.name);
+            res.push(Lint{code: LintCode::UnproductiveMachine, message, location: location.clone()});
+        }
+    }
+
+    let mut uses: HashMap<char, Vec<SourceLocation>> = HashMap::new();
+    for t in net.machines.iter().flat_map(|m| &m.states).flat_map(|s| &s.transitions).filter(|t| !t.is_nonterminal()) {
+        uses.entry(t.character).or_default().push(t.location.clone());
+    }
+    let mut single: Vec<(char, SourceLocation)> = uses.into_iter().filter(|(_, l)| l.len() == 1).map(|(c, mut l)| (c, l.remove(0))).collect();
+    single.sort_by_key(|(c, _)| *c);
+    for (c, location) in single {
+        let message = format!("terminal '{}' is used only once, is it a typo?", c);
+        res.push(Lint{code: LintCode::SingleUseTerminal, message, location});
+    }
+    res
+}
+
+/// Prints the lints at their configured level. Returns false when a
+/// denied lint fired.
+pub fn print_lints(net: &MachineNet, config: &LintConfig) -> bool {
+    let mut ok = true;
+    for l in lint(net) {
+        let level = config.level(l.code);
+        l.print(level);
+        ok &= level != LintLevel::Deny;
+    }
+    ok
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        let net = load_net(r"./tests/untidy.txt").unwrap();
+        let lints = lint(&net);
+        let codes: Vec<_> = lints.iter().filter(|l| l.code != LintCode::SingleUseTerminal).map(|l| (l.code, l.message.as_str())).collect();
+        assert_eq!(codes, vec![
+            (LintCode::UnreachableState, "state 3S is unreachable from state 0S"),
+            (LintCode::NonFinalSink, "state 4S has no outgoing arcs but is not final"),
+            (LintCode::UnusedMachine, "machine U is never called from S")
+        ]);
+        // Machine lints point at the machine declaration, not at its initial state
+        let unused = lints.iter().find(|l| l.code == LintCode::UnusedMachine).unwrap();
+        assert_eq!((unused.location.row, unused.location.col), (29, 12));
+        let mut config = LintConfig::default();
+        assert!(print_lints(&net, &config));
+        config.set(LintCode::UnusedMachine, LintLevel::Deny);
+        assert!(!print_lints(&net, &config));
+
+        let mut net = net;
+        net.machines[1].states[1].is_final = false;
+        let unproductive: Vec<_> = lint(&net).into_iter().filter(|l| l.code == LintCode::UnproductiveMachine).map(|l| (l.message, l.location.row)).collect();
+        assert_eq!(unproductive, vec![("machine A generates no terminal string".to_string(), 20)]);
+
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        assert!(lint(&net).iter().all(|l| l.code == LintCode::SingleUseTerminal));
+    }
+}
@@ -1,52 +1,974 @@
+mod ambiguity;
+mod analysis;
+mod classify;
+mod codegen;
+mod coverage;
+mod ell;
+mod elr_parser;
 mod elr_pilot;
 mod fsm;
+mod glr;
 mod lexer;
+mod lint;
 mod parser;
+mod regular;
+mod sentences;
+mod syntax_tree;
+mod transform;
 
+use std::collections::HashMap;
 use std::env::args;
 use std::path::Path;
 use std::process::exit;
 
+pub use crate::ambiguity::*;
+pub use crate::analysis::*;
+pub use crate::classify::*;
+pub use crate::codegen::*;
+pub use crate::coverage::*;
+pub use crate::ell::*;
+pub use crate::elr_parser::*;
 pub use crate::elr_pilot::*;
+pub use crate::glr::*;
 pub use crate::lexer::*;
+pub use crate::lint::*;
 pub use crate::parser::*;
+pub use crate::regular::*;
+pub use crate::sentences::*;
+pub use crate::syntax_tree::*;
+pub use crate::transform::*;
 
-fn generate_pilot(path: impl AsRef<Path>) {
-    let lex = Lexer::from_path(Path::new(path.as_ref()));
+/// A net file, and what the command line chose about the net in it.
+trait NetSource {
+    fn path(&self) -> &Path;
+
+    fn axiom(&self) -> Option<char> {
+        None
+    }
+
+    fn end_marker(&self) -> Option<char> {
+        None
+    }
+
+    /// Which of the nets of the file to load, needed when there are several.
+    fn net_name(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl NetSource for &str {
+    fn path(&self) -> &Path {
+        Path::new(*self)
+    }
+}
+
+impl NetSource for &Options {
+    fn path(&self) -> &Path {
+        Path::new(&self.path)
+    }
+
+    fn axiom(&self) -> Option<char> {
+        self.character("axiom")
+    }
+
+    fn end_marker(&self) -> Option<char> {
+        self.character("end")
+    }
+
+    fn net_name(&self) -> Option<&str> {
+        self.flag("net").then(|| self.text("net"))
+    }
+}
+
+fn select_net(nets: Vec<MachineNet>, name: Option<&str>) -> Option<MachineNet> {
+    let names: Vec<&str> = nets.iter().filter_map(|net| net.name.as_deref()).collect();
+    match name {
+        Some(name) if names.contains(&name) => nets.into_iter().find(|net| net.name.as_deref() == Some(name)),
+        Some(name) => {
+            eprintln!("error: no net named {} in the file", name);
+            None
+        }
+        None if nets.len() == 1 => nets.into_iter().next(),
+        None => {
+            eprintln!("error: the file holds several nets, choose one with --net=NAME");
+            eprintln!("note: the nets are {}", names.join(", "));
+            None
+        }
+    }
+}
+
+fn load_net(source: impl NetSource) -> Option<MachineNet> {
+    let lex = Lexer::from_path(source.path());
     let mut pars = Parser::new(lex);
-    if let Some(net) = pars.parse_mnet() {
-        if net.validate() {
-            let pilot = create_pilot(&net);
-            //println!("pilot: {pilot:?}");
-            println!("{}", pilot.to_dot());
-            pilot.print_conflicts();
+    let mut net = select_net(pars.parse_file()?, source.net_name())?;
+    net.axiom = source.axiom().unwrap_or(net.axiom);
+    net.end_marker = source.end_marker().unwrap_or(net.end_marker);
+    if net.validate() {
+        Some(net)
+    } else {
+        None
+    }
+}
+
+fn generate_lr0_pilot(source: impl NetSource) {
+    if let Some(net) = load_net(source) {
+        let pilot = create_lr0_pilot(&net);
+        println!("{}", pilot.to_dot(&net));
+        eprintln!("LR(0):");
+        pilot.print_lr0_conflicts(&net, None);
+        eprintln!("SLR(1):");
+        pilot.print_lr0_conflicts(&net, Some(&NetAnalysis::new(&net)));
+    }
+}
+
+fn generate_k_pilot(source: impl NetSource, k: usize) {
+    if let Some(net) = load_net(source) {
+        let analysis = KNetAnalysis::new(&net, k);
+        let pilot = create_k_pilot(&net, &analysis);
+        println!("{}", pilot.to_dot(&net));
+        if pilot.print_k_conflicts(&net, &analysis) == 0 {
+            eprintln!("the net is ELR({})", k);
+        } else {
+            eprintln!("the net is not ELR({})", k);
+        }
+    }
+}
+
+fn print_pilot(net: &MachineNet, lalr: bool) {
+    let mut pilot = create_pilot(net);
+    if lalr {
+        let compaction = pilot.compact();
+        compaction.print_report(&pilot, net);
+        pilot = compaction.pilot;
+    }
+    if !net.precedence.is_empty() {
+        let resolved = pilot.resolve_conflicts(net);
+        pilot.print_resolutions(&resolved, net);
+    }
+    //println!("pilot: {pilot:?}");
+    println!("{}", pilot.to_dot(net));
+    pilot.print_conflicts(net);
+}
+
+fn generate_pilot(source: impl NetSource, lalr: bool) {
+    if let Some(net) = load_net(source) {
+        print_pilot(&net, lalr);
+    }
+}
+
+/// Prints one pilot per entry point, each taken as the axiom in turn.
+fn generate_entry_pilots(source: impl NetSource, entries: &str, lalr: bool) {
+    let Some(net) = load_net(source) else {
+        return;
+    };
+    for entry in entries.split(',') {
+        let mut chars = entry.chars();
+        let (Some(axiom), None) = (chars.next(), chars.next()) else {
+            eprintln!("error: entry point {:?} is not a machine name", entry);
+            return;
+        };
+        let net = net.with_axiom(axiom);
+        if !net.validate() {
+            return;
+        }
+        eprintln!("entry point {}:", axiom);
+        print_pilot(&net, lalr);
+    }
+}
+
+fn generate_net_dot(source: impl NetSource, calls: bool) {
+    if let Some(net) = load_net(source) {
+        println!("{}", net.to_dot(calls));
+    }
+}
+
+fn print_analysis(source: impl NetSource, k: usize) {
+    if let Some(net) = load_net(source) {
+        if k == 1 {
+            NetAnalysis::new(&net).print_report(&net);
+        } else {
+            KNetAnalysis::new(&net, k).print_report(&net);
+        }
+    }
+}
+
+fn print_classification(source: impl NetSource) {
+    if let Some(net) = load_net(source) {
+        for verdict in classify(&net) {
+            verdict.print();
+        }
+    }
+}
+
+fn print_regular(source: impl NetSource, input: Option<&str>) {
+    let Some(net) = load_net(source) else {
+        return;
+    };
+    match flatten(&net) {
+        Ok(machine) => {
+            let machine = machine.minimize();
+            match input {
+                Some(word) => println!("{}", if machine.accepts(word) { "accepted" } else { "rejected" }),
+                None => {
+                    println!("the net is not self-embedding, its language is recognized by");
+                    print!("{}", machine);
+                }
+            }
+        }
+        Err(found) => {
+            eprintln!("error: the net is self-embedding, it cannot be flattened into one machine");
+            for e in found {
+                eprintln!("note: {}", e.describe(&net));
+            }
+        }
+    }
+}
+
+fn transform_net(source: impl NetSource, passes: &str) {
+    let Some(mut net) = load_net(source) else {
+        return;
+    };
+    for pass in passes.split(',').filter(|p| !p.is_empty()) {
+        let res = match pass.split_once(':') {
+            None if pass == "trim" => trim(&net),
+            None if pass == "renumber" => renumber(&net),
+            None if pass == "normalize" => normalize_initial(&net),
+            Some(("inline", machine)) if machine.chars().count() == 1 => inline_machine(&net, machine.chars().next().unwrap()),
+            _ => {
+                eprintln!("error: unknown pass {}", pass);
+                return;
+            }
+        };
+        let Some(res) = res else {
+            eprintln!("error: pass {} produced an invalid net", pass);
+            return;
+        };
+        for change in &res.changes {
+            eprintln!("note: {}: {}", pass, change);
+        }
+        net = res.net;
+    }
+    print!("{}", net);
+}
+
+/// Returns false when the net is invalid, a lint code is unknown or a
+/// denied lint fired.
+fn lint_net(source: impl NetSource, allow: &str, deny: &str) -> bool {
+    let mut config = LintConfig::default();
+    for (codes, level) in [(allow, LintLevel::Allow), (deny, LintLevel::Deny)] {
+        for name in codes.split(',').filter(|c| !c.is_empty()) {
+            let Some(code) = LintCode::from_name(name) else {
+                eprintln!("error: unknown lint {}", name);
+                return false;
+            };
+            config.set(code, level);
+        }
+    }
+    match load_net(source) {
+        Some(net) => print_lints(&net, &config),
+        None => false
+    }
+}
+
+fn check_ambiguity(source: impl NetSource, max_len: usize) {
+    if let Some(net) = load_net(source) {
+        print_ambiguities(&net, max_len);
+    }
+}
+
+fn display_sentence(s: &str) -> &str {
+    if s.is_empty() { "ε" } else { s }
+}
+
+fn generate_sentences(source: impl NetSource, count: usize, max_len: usize) {
+    if let Some(net) = load_net(source) {
+        for s in shortest_sentences(&net, count, max_len) {
+            println!("{}", display_sentence(&s));
+        }
+    }
+}
+
+fn sample_sentences(source: impl NetSource, count: usize, seed: u64, length: usize) {
+    if let Some(net) = load_net(source) {
+        let mut sampler = SentenceSampler::new(&net, seed, length);
+        for _ in 0..count {
+            match sampler.sample() {
+                Some(s) => println!("{}", display_sentence(&s)),
+                None => {
+                    eprintln!("error: the axiom generates no sentence");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn generate_coverage(source: impl NetSource, max_len: usize) {
+    if let Some(net) = load_net(source) {
+        let pilot = create_pilot(&net);
+        coverage_sentences(&pilot, &net, max_len).print(&pilot, &net);
+    }
+}
+
+/// Overrides the synchronizing terminals of some machines with a list like
+/// `A:bc,S:d`.
+fn parse_sync_sets(net: &MachineNet, spec: &str) -> Option<SyncSets> {
+    let mut res = follow_sync_sets(net);
+    for item in spec.split(',').filter(|i| !i.is_empty()) {
+        let (machine, terminals) = item.split_once(':')?;
+        let mut chars = machine.chars();
+        let machine = chars.next().filter(|_| chars.next().is_none())?;
+        net.try_lookup_machine(machine)?;
+        res.insert(machine, terminals.chars().collect());
+    }
+    Some(res)
+}
+
+fn parse_input(source: impl NetSource, input: &str, sync: &str) {
+    if let Some(net) = load_net(source) {
+        let mut pilot = create_pilot(&net);
+        if !net.precedence.is_empty() {
+            pilot.resolve_conflicts(&net);
+        }
+        let Some(sync) = parse_sync_sets(&net, sync) else {
+            eprintln!("error: invalid --sync list, expected e.g. A:bc,S:d");
+            return;
+        };
+        let res = parse_with_recovery(&pilot, input, &sync);
+        for e in &res.errors {
+            e.print(net.end_marker);
+        }
+        if let Some(tree) = res.tree {
+            println!("{}", tree);
+        }
+    }
+}
+
+fn simulate_ell(source: impl NetSource, input: &str) {
+    if let Some(net) = load_net(source) {
+        for step in ell_simulate(&net, &NetAnalysis::new(&net), input) {
+            step.print(&net);
+        }
+    }
+}
+
+fn parse_glr(source: impl NetSource, input: &str, max_trees: usize) {
+    if let Some(net) = load_net(source) {
+        let pilot = create_pilot(&net);
+        let parse = glr_parse(&pilot, input);
+        parse.print_forks(&pilot, &net);
+        if let Some(pos) = parse.error_position {
+            eprintln!("error: unexpected '{}' at position {}", input.chars().nth(pos).unwrap_or(net.end_marker), pos);
+        }
+        if parse.root.is_none() {
+            eprintln!("input rejected");
+            return;
+        }
+        print!("{}", parse.forest);
+        for (i, t) in parse.trees(max_trees).iter().enumerate() {
+            println!("tree {}: {}", i + 1, t);
+        }
+    }
+}
+
+fn generate_rust(source: impl NetSource, ell: bool) {
+    let path = source.path().display().to_string();
+    if let Some(net) = load_net(source) {
+        if ell {
+            let analysis = NetAnalysis::new(&net);
+            match ell_rust_module(&net, &analysis, &path) {
+                Some(module) => print!("{}", module),
+                None => {
+                    for c in guide_conflicts(&net, &analysis) {
+                        c.print(&net);
+                    }
+                    eprintln!("error: cannot generate a recursive-descent parser for a net that is not ELL(1)");
+                }
+            }
+            return;
+        }
+        let mut pilot = create_pilot(&net);
+        if !net.precedence.is_empty() {
+            pilot.resolve_conflicts(&net);
+        }
+        match elr_rust_module(&pilot, &path) {
+            Some(module) => print!("{}", module),
+            None => {
+                pilot.print_conflicts(&net);
+                eprintln!("error: cannot generate a deterministic parser for a pilot with conflicts");
+            }
+        }
+    }
+}
+
+struct Options {
+    command: String,
+    path: String,
+    values: HashMap<String, String>
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Options> {
+        let mut positional: Vec<String> = Vec::new();
+        let mut values: HashMap<String, String> = HashMap::new();
+        for arg in args {
+            if let Some(opt) = arg.strip_prefix("--") {
+                let (key, value) = opt.split_once('=').unwrap_or((opt, ""));
+                values.insert(key.to_string(), value.to_string());
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        let (command, path) = match positional.len() {
+            1 => ("pilot".to_string(), positional.remove(0)),
+            2 => (positional.remove(0), positional.remove(0)),
+            _ => return None
+        };
+        Some(Options{command, path, values})
+    }
+
+    /// The value of an option that must be a single character, if given.
+    fn character(&self, key: &str) -> Option<char> {
+        let value = self.values.get(key)?;
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => {
+                eprintln!("error: option --{} expects a single character", key);
+                exit(1);
+            }
         }
     }
+
+    fn flag(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    fn text(&self, key: &str) -> &str {
+        self.values.get(key).map_or("", |v| v.as_str())
+    }
+
+    fn number(&self, key: &str, default: usize) -> usize {
+        match self.values.get(key) {
+            Some(v) => v.parse().unwrap_or_else(|_| {
+                eprintln!("error: option --{} expects a number", key);
+                exit(1);
+            }),
+            None => default
+        }
+    }
+}
+
+fn usage(prog: &str) -> ! {
+    eprintln!("usage: {} [command] [options] [file]", prog);
+    eprintln!("commands:");
+    eprintln!("  pilot      print the ELR(1) pilot in DOT format and its conflicts (default)");
+    eprintln!("             --lalr merges states with equal kernels");
+    eprintln!("             shift-reduce conflicts are resolved by %left, %right and %nonassoc declarations");
+    eprintln!("             --lr0 builds the pilot without lookaheads and checks LR(0) and SLR(1)");
+    eprintln!("             --k=N builds the ELR(k) pilot with lookahead strings of N terminals");
+    eprintln!("             --entries=E,T builds one pilot for each listed machine taken as the axiom");
+    eprintln!("  net        print the machine net in DOT format, one cluster per machine");
+    eprintln!("             --calls adds a dashed edge from each call to the initial state of the callee");
+    eprintln!("  analysis   print nullable machines, initials and follow sets (--k=N for Ini_k and Follow_k)");
+    eprintln!("  classify   tell whether the net is regular, ELL(1), ELR(1), LR(0) or ambiguous, with evidence");
+    eprintln!("  regular    print the minimal machine of a net that is not self-embedding");
+    eprintln!("             --input=WORD only tells whether it accepts WORD");
+    eprintln!("  transform  apply --pass=P1,P2,... and print the resulting net, logging the changes");
+    eprintln!("             passes: trim, renumber, normalize (no arcs into initial states), inline:A");
+    eprintln!("  lint       warn about suspicious states, machines and terminals");
+    eprintln!("             --allow=CODE,... silences lints, --deny=CODE,... turns them into errors");
+    eprintln!("             codes: unreachable-state, dead-state, unused-machine, unproductive-machine,");
+    eprintln!("             non-final-sink, single-use-terminal");
+    eprintln!("  ambiguity  search for sentences with two syntax trees (--max-len=N, default 6)");
+    eprintln!("  generate   list the shortest sentences (--count=N, default 20; --max-len=N, default 12)");
+    eprintln!("  coverage   pick sentences covering the pilot (--max-len=N, default 10)");
+    eprintln!("  parse      parse --input=WORD, reporting every error with panic-mode recovery");
+    eprintln!("             --sync=A:bc,S:d sets the synchronizing terminals of machines (default Follow)");
+    eprintln!("  codegen    print a standalone Rust module parsing the net with the pilot tables");
+    eprintln!("             --ell emits a recursive-descent parser driven by guide sets instead");
+    eprintln!("  ell        trace a top-down parse of --input=WORD with guide sets and return states");
+    eprintln!("  glr        parse --input=WORD following every conflict, printing forks, forest and trees");
+    eprintln!("             (--max-trees=N, default 10)");
+    eprintln!("  sample     print random sentences (--count=N, default 10; --seed=N; --length=N, default 10)");
+    eprintln!("options of every command:");
+    eprintln!("  --axiom=A  take machine A as the axiom instead of the one of the file (default S)");
+    eprintln!("  --end=C    use C as the end-of-input marker instead of the one of the file (default $)");
+    eprintln!("  --net=N    load the net named N, for files holding several");
+    exit(1);
 }
 
 fn main() {
     let args: Vec<_> = args().collect();
-    if args.len() != 2 {
-        eprintln!("usage: {} [file]", args[0]);
-        exit(1);
+    let Some(opts) = Options::parse(&args[1..]) else {
+        usage(&args[0]);
+    };
+    match opts.command.as_str() {
+        "pilot" if opts.flag("lr0") => generate_lr0_pilot(&opts),
+        "pilot" if opts.flag("k") => generate_k_pilot(&opts, opts.number("k", 1)),
+        "pilot" if opts.flag("entries") => generate_entry_pilots(&opts, opts.text("entries"), opts.flag("lalr")),
+        "pilot" => generate_pilot(&opts, opts.flag("lalr")),
+        "net" => generate_net_dot(&opts, opts.flag("calls")),
+        "analysis" => print_analysis(&opts, opts.number("k", 1)),
+        "classify" => print_classification(&opts),
+        "lint" => if !lint_net(&opts, opts.text("allow"), opts.text("deny")) {
+            exit(1);
+        },
+        "transform" => transform_net(&opts, opts.text("pass")),
+        "regular" => print_regular(&opts, opts.flag("input").then(|| opts.text("input"))),
+        "ambiguity" => check_ambiguity(&opts, opts.number("max-len", 6)),
+        "generate" => generate_sentences(&opts, opts.number("count", 20), opts.number("max-len", 12)),
+        "coverage" => generate_coverage(&opts, opts.number("max-len", 10)),
+        "parse" => parse_input(&opts, opts.text("input"), opts.text("sync")),
+        "codegen" => generate_rust(&opts, opts.flag("ell")),
+        "ell" => simulate_ell(&opts, opts.text("input")),
+        "glr" => parse_glr(&opts, opts.text("input"), opts.number("max-trees", 10)),
+        "sample" => {
+            let seed = opts.number("seed", 0) as u64;
+            sample_sentences(&opts, opts.number("count", 10), seed, opts.number("length", 10))
+        }
+        _ => usage(&args[0])
     }
-    generate_pilot(&args[1]);
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::Path;
+    use crate::classify;
+    use crate::create_k_pilot;
+    use crate::create_lr0_pilot;
+    use crate::create_pilot;
+    use crate::ell_rust_module;
+    use crate::ell_simulate;
+    use crate::EllMove;
+    use crate::elr_rust_module;
+    use crate::find_ambiguities;
+    use crate::find_self_embeddings;
+    use crate::flatten;
+    use crate::follow_sync_sets;
     use crate::generate_pilot;
+    use crate::guide_conflicts;
+    use crate::KNetAnalysis;
+    use crate::LanguageSlices;
+    use crate::lint_net;
+    use crate::load_net;
+    use crate::MachineNet;
+    use crate::NetAnalysis;
+    use crate::normalize_initial;
+    use crate::Options;
+    use crate::parse_sync_sets;
+    use crate::parse_with_recovery;
+    use crate::renumber;
+    use crate::SentenceSampler;
+    use crate::shortest_sentences;
+    use crate::syntax_trees;
+    use crate::SyntaxTree;
+    use crate::trim;
+
+    const NETS: [&str; 23] = [
+        r"./tests/cursed.txt",
+        r"./tests/dangling_else.txt",
+        r"./tests/dangling_else_prec.txt",
+        r"./tests/elr_2.txt",
+        r"./tests/expr_entry.txt",
+        r"./tests/expr_prec.txt",
+        r"./tests/import_expr.txt",
+        r"./tests/include_expr.txt",
+        r"./tests/lalr_reduce_reduce.txt",
+        r"./tests/left_recursion.txt",
+        r"./tests/linear_recursion.txt",
+        r"./tests/named_states.txt",
+        r"./tests/right_recursion.txt",
+        r"./tests/lookahead_context.txt",
+        r"./tests/shift_to_initial.txt",
+        r"./tests/untidy.txt",
+        r"./tests/elr_mnet_2013-02-05.txt",
+        r"./tests/elr_mnet_2020-01-14.txt",
+        r"./tests/elr_mnet_2024-02-13.txt",
+        r"./tests/elr_mnet_2024-06-13.txt",
+        r"./tests/elr_mnet_2024-07-04.txt",
+        r"./tests/elr_mnet_book-4.15.txt",
+        r"./tests/elr_mnet_book-4.16.txt",
+    ];
+
+    fn terminals(net: &MachineNet) -> Vec<char> {
+        let mut res: Vec<char> = net.machines.iter()
+            .flat_map(|m| m.states.iter().flat_map(|s| s.transitions.iter()))
+            .filter(|t| !t.is_nonterminal())
+            .map(|t| t.character)
+            .collect();
+        res.sort();
+        res.dedup();
+        res
+    }
 
     #[test]
     fn test_generate_pilot() {
-        generate_pilot(r"./tests/cursed.txt");
-        generate_pilot(r"./tests/dangling_else.txt");
-        generate_pilot(r"./tests/elr_mnet_2013-02-05.txt");
-        generate_pilot(r"./tests/elr_mnet_2020-01-14.txt");
-        generate_pilot(r"./tests/elr_mnet_2024-02-13.txt");
-        generate_pilot(r"./tests/elr_mnet_2024-06-13.txt");
-        generate_pilot(r"./tests/elr_mnet_2024-07-04.txt");
-        generate_pilot(r"./tests/elr_mnet_book-4.15.txt");
-        generate_pilot(r"./tests/elr_mnet_book-4.16.txt");
+        for net in NETS {
+            generate_pilot(net, false);
+        }
+    }
+
+    #[test]
+    fn test_pilot_variants() {
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let pilot = create_pilot(&net);
+            // LALR merging leaves one state per LR(0) kernel
+            assert_eq!(pilot.compact().pilot.states.len(), create_lr0_pilot(&net).states.len(), "{}", path);
+            let k1 = create_k_pilot(&net, &KNetAnalysis::new(&net, 1));
+            let candidates: Vec<Vec<_>> = pilot.states.iter().map(|s| {
+                s.candidates.iter().map(|c| (c.machine, c.state, c.lookahead.to_string())).collect()
+            }).collect();
+            let k1_candidates: Vec<Vec<_>> = k1.states.iter().map(|s| {
+                s.candidates.iter().map(|c| (c.machine, c.state, c.lookahead.clone())).collect()
+            }).collect();
+            assert_eq!(k1_candidates, candidates, "{}", path);
+            let k2 = create_k_pilot(&net, &KNetAnalysis::new(&net, 2));
+            assert!(k2.states.len() >= pilot.states.len());
+        }
+    }
+
+    /// Checks that the terminal following every machine run in `tree`,
+    /// which starts at `pos` of `sentence`, is in the follow set of the
+    /// machine. Returns the position after the tree.
+    fn check_follow(tree: &SyntaxTree, pos: usize, sentence: &[char], net: &MachineNet, analysis: &NetAnalysis) -> usize {
+        match tree {
+            SyntaxTree::Leaf(_) => pos + 1,
+            SyntaxTree::Node(machine, children) => {
+                let end = children.iter().fold(pos, |p, c| check_follow(c, p, sentence, net, analysis));
+                let next = sentence.get(end).copied().unwrap_or(net.end_marker);
+                assert!(analysis.follow(*machine).contains(&next), "{} not in Follow({})", next, machine);
+                end
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_analysis() {
+        for path in NETS {
+            // Ini follows the arcs even into dead states, which have no strings
+            let net = trim(&load_net(path).unwrap()).unwrap().net;
+            let analysis = NetAnalysis::new(&net);
+            let (k1, k2) = (KNetAnalysis::new(&net, 1), KNetAnalysis::new(&net, 2));
+            let slices = LanguageSlices::new(&net, 6);
+            for m in &net.machines {
+                for s in &m.states {
+                    let strings: Vec<&String> = (0..=6).flat_map(|len| slices.strings(m.name, s.id, len)).collect();
+                    assert_eq!(analysis.is_state_nullable(m.name, s.id), strings.contains(&&String::new()));
+                    let initials: HashSet<char> = strings.iter().filter_map(|w| w.chars().next()).collect();
+                    assert_eq!(analysis.initials(m.name, s.id), &initials, "{} {}{}", path, s.name, m.name);
+                    let prefixes: HashSet<String> = strings.iter().map(|w| w.chars().take(2).collect()).collect();
+                    assert_eq!(k2.initials(m.name, s.id), &prefixes, "{} {}{}", path, s.name, m.name);
+                }
+                let follow: HashSet<String> = analysis.follow(m.name).iter().map(|c| c.to_string()).collect();
+                assert_eq!(k1.follow(m.name), &follow, "{} {}", path, m.name);
+            }
+            for sentence in slices.sentences().iter().filter(|s| s.len() <= 4) {
+                let chars: Vec<char> = sentence.chars().collect();
+                for tree in syntax_trees(&net, sentence, 2) {
+                    check_follow(&tree, 0, &chars, &net, &analysis);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_classification() {
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let verdicts = classify(&net);
+            let [regular, ell, elr, lr0, ambiguous] = [0, 1, 2, 3, 4].map(|i| verdicts[i].holds);
+            assert_eq!(regular, flatten(&net).is_ok(), "{}", path);
+            assert_eq!(ell, guide_conflicts(&net, &NetAnalysis::new(&net)).is_empty(), "{}", path);
+            assert_eq!(ambiguous, !find_ambiguities(&net, 6).is_empty(), "{}", path);
+            assert!(elr || !(ell || lr0), "{}", path);
+            assert!(!(elr && ambiguous), "{}", path);
+            assert!(verdicts.iter().all(|v| !v.evidence.is_empty()));
+        }
+    }
+
+    #[test]
+    fn test_print_regular() {
+        let mut flattened: Vec<&str> = Vec::new();
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let Ok(machine) = flatten(&net) else {
+                assert!(!find_self_embeddings(&net).is_empty());
+                continue;
+            };
+            flattened.push(path);
+            let machine = machine.minimize();
+            let sentences = LanguageSlices::new(&net, 5).sentences();
+            let mut words = vec![String::new()];
+            for len in 0..5 {
+                for w in words.clone().iter().filter(|w| w.len() == len) {
+                    words.extend(terminals(&net).iter().map(|c| format!("{}{}", w, c)));
+                }
+            }
+            for w in &words {
+                assert_eq!(machine.accepts(w), sentences.contains(w), "{} on {}", path, w);
+            }
+        }
+        assert_eq!(flattened, vec![
+            r"./tests/elr_2.txt",
+            r"./tests/lalr_reduce_reduce.txt",
+            r"./tests/left_recursion.txt",
+            r"./tests/linear_recursion.txt",
+            r"./tests/right_recursion.txt",
+            r"./tests/lookahead_context.txt",
+            r"./tests/shift_to_initial.txt",
+            r"./tests/untidy.txt",
+        ]);
+    }
+
+    #[test]
+    fn test_transform_net() {
+        let dir = std::env::temp_dir().join(format!("flc_transform_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let trimmed = trim(&net).unwrap();
+            let normalized = normalize_initial(&trimmed.net).unwrap();
+            let renumbered = renumber(&normalized.net).unwrap();
+            for m in &renumbered.net.machines {
+                assert_eq!(m.initial_id(), 0);
+                assert!(m.states.iter().enumerate().all(|(i, s)| s.id == i as i32 && s.name == s.id.to_string()));
+                assert!(m.states.iter().flat_map(|s| &s.transitions).all(|t| t.dest_id != 0));
+            }
+            // The printed net must load back with the same language
+            let printed = dir.join(Path::new(path).file_name().unwrap());
+            fs::write(&printed, renumbered.net.to_string()).unwrap();
+            let reloaded = load_net(printed.to_str().unwrap()).unwrap();
+            assert_eq!(LanguageSlices::new(&reloaded, 6).sentences(), LanguageSlices::new(&net, 6).sentences(), "{}", path);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lint_net() {
+        for net in NETS {
+            assert!(lint_net(net, "single-use-terminal", ""));
+        }
+        assert!(!lint_net(r"./tests/untidy.txt", "", "unused-machine"));
+        assert!(!lint_net(r"./tests/untidy.txt", "no-such-lint", ""));
+    }
+
+    #[test]
+    fn test_net_selection() {
+        assert!(load_net(r"./tests/variants.txt").is_none());
+        for (name, left_recursive) in [("Left", true), ("Right", false)] {
+            let args: Vec<String> = vec!["pilot".into(), format!("--net={}", name), r"./tests/variants.txt".into()];
+            let net = load_net(&Options::parse(&args).unwrap()).unwrap();
+            assert_eq!(net.name.as_deref(), Some(name));
+            assert_eq!(net.lookup_state('S', 0).try_lookup_transition('S').is_some(), left_recursive);
+        }
+        let args: Vec<String> = vec!["pilot".into(), "--net=Middle".into(), r"./tests/variants.txt".into()];
+        assert!(load_net(&Options::parse(&args).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        assert!(load_net(r"./tests/unterminated_comment.txt").is_none());
+    }
+
+    #[test]
+    fn test_undeclared_state() {
+        // 1 is not declared and must not resolve to q_end, numbered 1
+        assert!(load_net(r"./tests/undeclared_state.txt").is_none());
+    }
+
+    #[test]
+    fn test_include_and_import() {
+        let imported = load_net(r"./tests/import_expr.txt").unwrap();
+        let original = load_net(r"./tests/expr_entry.txt").unwrap();
+        assert_eq!(imported.lookup_state('F', 2).try_lookup_transition('S').unwrap().dest_id, 3);
+        assert_eq!(create_pilot(&imported).states.len(), create_pilot(&original).states.len());
+        let included = load_net(r"./tests/include_expr.txt").unwrap();
+        assert_eq!(included.machines.len(), 4);
+        assert!(load_net(r"./tests/include_clash.txt").is_none());
+    }
+
+    #[test]
+    fn test_generate_net_dot() {
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let arcs: Vec<_> = net.machines.iter().flat_map(|m| &m.states).flat_map(|s| &s.transitions).collect();
+            let calls = arcs.iter().filter(|t| t.is_nonterminal()).count();
+            let dot = net.to_dot(false);
+            let nodes = dot.lines().filter(|l| l.contains("shape=circle") || l.contains("shape=doublecircle")).count();
+            assert_eq!(nodes, net.machines.iter().map(|m| m.states.len()).sum::<usize>());
+            assert_eq!(dot.lines().filter(|l| l.contains("[label=") && l.contains(" -> ")).count(), arcs.len());
+            assert_eq!(dot.matches("style=bold").count(), calls);
+            assert_eq!(dot.matches("_entry\" -> ").count(), net.machines.len());
+            assert_eq!(net.to_dot(true).matches("style=dashed").count(), calls);
+        }
+        let net = load_net(r"./tests/named_states.txt").unwrap();
+        let dot = net.to_dot(true);
+        assert!(dot.contains("\"S_entry\" -> \"S0\";"));
+        assert!(dot.contains("\"A9\" [label=\"9\", shape=doublecircle];"));
+        assert!(dot.contains("\"A7\" -> \"A6\" [style=dashed"));
+        assert!(!net.to_dot(false).contains("dashed"));
+    }
+
+    #[test]
+    fn test_generate_entry_pilots() {
+        let net = load_net(r"./tests/expr_entry.txt").unwrap();
+        for axiom in ['E', 'T', 'F'] {
+            let entry = net.with_axiom(axiom);
+            assert!(entry.validate());
+            let pilot = create_pilot(&entry);
+            let start = &pilot.lookup_state(0).candidates[0];
+            assert_eq!((start.machine, start.state, start.lookahead), (axiom, 0, '#'));
+            assert!(pilot.states.iter().all(|s| s.shift_reduce_conflicts().is_empty() && s.reduce_reduce_conflicts().is_empty()));
+        }
+        assert!(!net.with_axiom('X').validate());
+        // The convergence conflict of S is reachable from A as well
+        let net = load_net(r"./tests/elr_mnet_book-4.16.txt").unwrap();
+        for axiom in ['S', 'A'] {
+            let pilot = create_pilot(&net.with_axiom(axiom)).compact().pilot;
+            let conflicts: Vec<_> = pilot.states.iter().flat_map(|s| s.convergence_conflicts()).map(|c| c.transition_char).collect();
+            assert_eq!(conflicts, vec!['c']);
+        }
+    }
+
+    #[test]
+    fn test_check_ambiguity() {
+        let mut shortest: Vec<(&str, String)> = Vec::new();
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let found = find_ambiguities(&net, 5);
+            for amb in &found {
+                assert_ne!(amb.trees[0], amb.trees[1]);
+                assert!(amb.trees.iter().all(|t| t.frontier() == amb.sentence));
+            }
+            if let Some(amb) = found.first() {
+                shortest.push((path, amb.sentence.clone()));
+            }
+        }
+        let expected = [
+            (r"./tests/cursed.txt", "aaa"),
+            (r"./tests/dangling_else.txt", "aacbc"),
+            (r"./tests/dangling_else_prec.txt", "aacbc"),
+            (r"./tests/expr_prec.txt", "npnpn"),
+            (r"./tests/left_recursion.txt", "b"),
+            (r"./tests/elr_mnet_2013-02-05.txt", "a"),
+            (r"./tests/elr_mnet_2020-01-14.txt", "aa"),
+            (r"./tests/elr_mnet_2024-07-04.txt", "abcc"),
+        ];
+        assert_eq!(shortest, expected.map(|(path, s)| (path, s.to_string())));
+    }
+
+    #[test]
+    fn test_generate_sentences() {
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let sentences = LanguageSlices::new(&net, 8).sentences();
+            let shortest = shortest_sentences(&net, 10, 8);
+            assert_eq!(shortest, sentences[..shortest.len()]);
+            assert!(shortest.len() == 10 || shortest.len() == sentences.len());
+            let mut sampler = SentenceSampler::new(&net, 1, 8);
+            for _ in 0..5 {
+                let s = sampler.sample().unwrap();
+                assert!(sentences.contains(&s), "{} samples {}", path, s);
+            }
+        }
+    }
+
+    #[test]
+    fn test_simulate_ell() {
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let analysis = NetAnalysis::new(&net);
+            if !guide_conflicts(&net, &analysis).is_empty() {
+                continue;
+            }
+            let sentences = LanguageSlices::new(&net, 6).sentences();
+            for s in sentences.iter().filter(|s| s.len() < 6) {
+                let steps = ell_simulate(&net, &analysis, s);
+                assert_eq!(steps.last().unwrap().chosen, EllMove::Accept, "{} rejects {}", path, s);
+                assert!(steps.iter().all(|step| step.also_possible.is_empty()));
+                for c in terminals(&net) {
+                    let longer = format!("{}{}", s, c);
+                    if !sentences.contains(&longer) {
+                        let steps = ell_simulate(&net, &analysis, &longer);
+                        assert!(matches!(steps.last().unwrap().chosen, EllMove::Error(_)), "{} accepts {}", path, longer);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_input() {
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let mut pilot = create_pilot(&net);
+            if !net.precedence.is_empty() {
+                pilot.resolve_conflicts(&net);
+            }
+            if pilot.states.iter().any(|s| !s.shift_reduce_conflicts().is_empty() || !s.reduce_reduce_conflicts().is_empty()) {
+                continue;
+            }
+            let sync = follow_sync_sets(&net);
+            let sentences = LanguageSlices::new(&net, 6).sentences();
+            for s in sentences.iter().filter(|s| s.len() < 6) {
+                let res = parse_with_recovery(&pilot, s, &sync);
+                assert!(res.errors.is_empty(), "{} rejects {}", path, s);
+                assert_eq!(res.tree.unwrap().frontier(), *s);
+                let longer = format!("{}?", s);
+                let res = parse_with_recovery(&pilot, &longer, &sync);
+                assert_eq!((res.errors[0].position, res.errors[0].found), (s.len(), '?'), "{}", path);
+            }
+        }
+        let net = load_net(r"./tests/dangling_else.txt").unwrap();
+        assert_eq!(parse_sync_sets(&net, "S:$,B:c").unwrap()[&'B'], HashSet::from(['c']));
+        assert!(parse_sync_sets(&net, "S:$,B").is_none());
+    }
+
+    #[test]
+    fn test_generate_rust() {
+        let (mut elr, mut ell) = (Vec::new(), Vec::new());
+        for path in NETS {
+            let net = load_net(path).unwrap();
+            let mut pilot = create_pilot(&net);
+            if !net.precedence.is_empty() {
+                pilot.resolve_conflicts(&net);
+            }
+            if elr_rust_module(&pilot, path).is_some() {
+                elr.push(path);
+            }
+            if ell_rust_module(&net, &NetAnalysis::new(&net), path).is_some() {
+                ell.push(path);
+            }
+        }
+        assert_eq!(elr, vec![
+            r"./tests/dangling_else_prec.txt",
+            r"./tests/expr_entry.txt",
+            r"./tests/expr_prec.txt",
+            r"./tests/import_expr.txt",
+            r"./tests/include_expr.txt",
+            r"./tests/lalr_reduce_reduce.txt",
+            r"./tests/linear_recursion.txt",
+            r"./tests/right_recursion.txt",
+            r"./tests/lookahead_context.txt",
+            r"./tests/shift_to_initial.txt",
+            r"./tests/untidy.txt",
+            r"./tests/elr_mnet_2024-02-13.txt",
+            r"./tests/elr_mnet_2024-06-13.txt",
+        ]);
+        assert_eq!(ell, vec![
+            r"./tests/expr_entry.txt",
+            r"./tests/import_expr.txt",
+            r"./tests/include_expr.txt",
+            r"./tests/right_recursion.txt",
+            r"./tests/lookahead_context.txt",
+            r"./tests/shift_to_initial.txt",
+            r"./tests/untidy.txt",
+            r"./tests/elr_mnet_2024-02-13.txt",
+            r"./tests/elr_mnet_2024-06-13.txt",
+        ]);
     }
 }
@@ -1,11 +1,17 @@
+use std::fs;
 use std::mem::replace;
+use std::path::Path;
+use std::path::PathBuf;
 
 use crate::lexer::*;
 use crate::elr_pilot::*;
+use crate::elr_pilot::precedence::*;
 
 pub struct Parser {
     lexer: Lexer,
-    lookahead: Option<Token>
+    lookahead: Option<Token>,
+    /// Files whose parsing led to this one, to detect include cycles.
+    including: Vec<PathBuf>
 }
 
 macro_rules! token {
@@ -43,7 +49,7 @@ macro_rules! accept {
 impl Parser {
     pub fn new(mut lexer: Lexer) -> Parser {
         let lookahead = lexer.next();
-        Parser{lexer, lookahead}
+        Parser{lexer, lookahead, including: vec![]}
     }
 
     fn emit_error(&self, s: &str) {
@@ -55,76 +61,364 @@ impl Parser {
     }
 
     fn advance(&mut self) -> Option<Token> {
-        if let Some(_) = self.lookahead {
+        if self.lookahead.is_some() {
             replace(&mut self.lookahead, self.lexer.next())
         } else {
             None
         }
     }
 
-    fn parse_state(&mut self) -> Option<State> {
+    fn location(&self) -> SourceLocation {
+        if let Some(look) = &self.lookahead {
+            look.location.clone()
+        } else {
+            self.lexer.location()
+        }
+    }
+
+    fn parse_state_name(&mut self, err: &str) -> Option<String> {
+        let name = match &self.lookahead {
+            token!(TokenValue::Number(num)) => num.to_string(),
+            token!(TokenValue::Ident(c)) => c.to_string(),
+            token!(TokenValue::Name(name)) => name.clone(),
+            _ => {
+                self.emit_error(err);
+                return None;
+            }
+        };
+        self.advance();
+        Some(name)
+    }
+
+    /// Parses a state whose id is left to `parse_machine`; the names of the
+    /// destinations of its transitions are returned alongside it.
+    fn parse_state(&mut self) -> Option<(State, Vec<String>)> {
+        let location = self.location();
         expect!(self, TokenValue::KwState, "expected a state");
-        let id = expect!(self, TokenValue::Number(num), "expected the state identifier", { num });
-        let mut state = State{id, transitions:vec![], is_initial:false, is_final:false};
+        let name = self.parse_state_name("expected the state identifier")?;
+        let mut state = State{id:-1, name, transitions:vec![], is_initial:false, is_final:false, location};
+        let mut dest_names: Vec<String> = Vec::new();
+        // The properties can also be listed in the header: state 0 initial final { ... }
+        loop {
+            if accept!(self, TokenValue::KwInitial).is_some() {
+                state.is_initial = true;
+            } else if accept!(self, TokenValue::KwFinal).is_some() {
+                state.is_final = true;
+            } else {
+                break;
+            }
+        }
         expect!(self, TokenValue::LBrace, "expected a state body enclosed in {}");
         loop {
-            if let Some(_) = accept!(self, TokenValue::KwInitial) {
+            if accept!(self, TokenValue::KwInitial).is_some() {
                 expect!(self, TokenValue::Semi, "expected semicolon");
                 state.is_initial = true;
-            } else if let Some(_) = accept!(self, TokenValue::KwFinal) {
+            } else if accept!(self, TokenValue::KwFinal).is_some() {
                 expect!(self, TokenValue::Semi, "expected semicolon");
                 state.is_final = true;
             } else if let token!(TokenValue::Ident(character)) = self.lookahead {
+                let location = self.location();
                 self.advance();
                 expect!(self, TokenValue::RArrow, "expected -> after transition character");
-                expect!(self, TokenValue::Number(dest_id), "expected transition destination state", {
-                    let trans = Transition{character, dest_id};
-                    state.transitions.push(trans);
-                });
+                dest_names.push(self.parse_state_name("expected transition destination state")?);
+                state.transitions.push(Transition{character, dest_id:-1, location});
                 expect!(self, TokenValue::Semi, "expected semicolon");
             } else {
                 break;
             }
         }
         expect!(self, TokenValue::RBrace, "expected a transition or a state property");
-        Some(state)
+        Some((state, dest_names))
+    }
+
+    /// Gives numeric state names their number as id and the other names
+    /// the next free ids, then resolves the transition destinations.
+    fn number_states(machine: &mut Machine, dest_names: Vec<Vec<String>>) -> Option<()> {
+        let mut next_id = machine.states.iter().filter_map(|s| s.name.parse::<i32>().ok()).max().map_or(0, |id| id + 1);
+        for i in 0 .. machine.states.len() {
+            let (before, after) = machine.states.split_at_mut(i);
+            let state = &mut after[0];
+            if let Some(other) = before.iter().find(|s| s.name == state.name) {
+                state.location.emit_error(&format!("state {}{} is declared twice", state.name, machine.name));
+                other.location.emit_note("first declared here");
+                return None;
+            }
+            state.id = match state.name.parse() {
+                Ok(id) => id,
+                Err(_) => {
+                    next_id += 1;
+                    next_id - 1
+                }
+            };
+        }
+        let ids: Vec<(String, i32)> = machine.states.iter().map(|s| (s.name.clone(), s.id)).collect();
+        for (state, names) in machine.states.iter_mut().zip(dest_names) {
+            for (t, name) in state.transitions.iter_mut().zip(names) {
+                // Named states take ids past the numeric ones, so an undeclared
+                // number could otherwise land on one of them
+                let Some((_, id)) = ids.iter().find(|(n, _)| *n == name) else {
+                    t.location.emit_error(&format!("state {}{} is not declared", name, machine.name));
+                    return None;
+                };
+                t.dest_id = *id;
+            }
+        }
+        Some(())
+    }
+
+    fn parse_precedence(&mut self, precedence: &mut Precedence) -> Option<()> {
+        let assoc = if accept!(self, TokenValue::KwLeft).is_some() {
+            Associativity::Left
+        } else if accept!(self, TokenValue::KwRight).is_some() {
+            Associativity::Right
+        } else {
+            expect!(self, TokenValue::KwNonAssoc, "expected a precedence declaration");
+            Associativity::NonAssoc
+        };
+        let mut terminals: Vec<char> = Vec::new();
+        while let token!(TokenValue::Ident(character)) = self.lookahead {
+            if !character.is_ascii_lowercase() {
+                self.emit_error("only terminals can have a precedence");
+                return None;
+            }
+            terminals.push(character);
+            self.advance();
+        }
+        if terminals.is_empty() {
+            self.emit_error("expected a list of terminals");
+            return None;
+        }
+        expect!(self, TokenValue::Semi, "expected semicolon");
+        precedence.declare(assoc, &terminals);
+        Some(())
     }
 
-    fn parse_machine(&mut self) -> Option<Machine> {
+    fn parse_machine(&mut self, precedence: &mut Precedence) -> Option<Machine> {
         expect!(self, TokenValue::KwMachine, "expected a machine");
-        let name = expect!(self, TokenValue::Ident(name), "expected a machine name", {
+        let location = self.location();
+        let name = self.parse_machine_name("expected a machine name")?;
+        if accept!(self, TokenValue::KwPrec).is_some() {
+            let location = self.location();
+            expect!(self, TokenValue::Ident(character), "expected the terminal giving the precedence", {
+                precedence.machines.insert(name, (character, location));
+            });
+        }
+        let mut machine = Machine{name, states: vec![], location};
+        let mut dest_names: Vec<Vec<String>> = Vec::new();
+        expect!(self, TokenValue::LBrace, "expected a machine body enclosed by {}");
+        while let token!(TokenValue::KwState) = self.lookahead {
+            let (state, names) = self.parse_state()?;
+            machine.states.push(state);
+            dest_names.push(names);
+        }
+        expect!(self, TokenValue::RBrace, "expected a list of states");
+        Parser::number_states(&mut machine, dest_names)?;
+        Some(machine)
+    }
+
+    fn parse_axiom(&mut self) -> Option<char> {
+        expect!(self, TokenValue::KwStart, "expected %start");
+        let axiom = expect!(self, TokenValue::Ident(name), "expected the name of the axiom", {
             if !name.is_ascii_uppercase() {
-                self.emit_error("machine name must be ASCII uppercase");
+                self.emit_error("the axiom must be a machine name");
                 return None;
             } else {
                 name
             }
         });
-        let mut machine = Machine{name, states: vec![]};
-        expect!(self, TokenValue::LBrace, "expected a machine body enclosed by {}");
-        while let token!(TokenValue::KwState) = self.lookahead {
-            if let Some(state) = self.parse_state() {
-                machine.states.push(state);
+        expect!(self, TokenValue::Semi, "expected semicolon");
+        Some(axiom)
+    }
+
+    fn parse_end_marker(&mut self) -> Option<char> {
+        expect!(self, TokenValue::KwEnd, "expected %end");
+        let end_marker = match self.lookahead {
+            token!(TokenValue::Ident(c)) | token!(TokenValue::Char(c)) => c,
+            _ => {
+                self.emit_error("expected the end marker, as a letter or a quoted character");
+                return None;
+            }
+        };
+        self.advance();
+        expect!(self, TokenValue::Semi, "expected semicolon");
+        Some(end_marker)
+    }
+
+    /// Parses the only net of the file at `path`, relative to the file
+    /// being parsed. Only its machines are taken: its precedences, axiom
+    /// and end marker are left out.
+    fn parse_other_file(&self, path: &str, location: &SourceLocation) -> Option<MachineNet> {
+        let path = self.lexer.path().parent().unwrap_or(Path::new("")).join(path);
+        let lexer = match Lexer::try_from_path(&path) {
+            Ok(lexer) => lexer,
+            Err(why) => {
+                location.emit_error(&format!("cannot read {}: {}", path.display(), why));
+                return None;
+            }
+        };
+        let canonical = fs::canonicalize(&path).unwrap_or(path.clone());
+        let mut including = self.including.clone();
+        including.push(fs::canonicalize(self.lexer.path()).unwrap_or(self.lexer.path().to_path_buf()));
+        if including.contains(&canonical) {
+            location.emit_error(&format!("{} includes itself", path.display()));
+            return None;
+        }
+        let mut parser = Parser::new(lexer);
+        parser.including = including;
+        let mut nets = parser.parse_file()?;
+        if nets.len() > 1 {
+            location.emit_error(&format!("{} holds several nets, only machines of a single net can be taken", path.display()));
+            return None;
+        }
+        nets.pop()
+    }
+
+    fn parse_file_path(&mut self) -> Option<String> {
+        let path = expect!(self, TokenValue::Str(ref path), "expected a file path in double quotes", { path.clone() });
+        expect!(self, TokenValue::Semi, "expected semicolon");
+        Some(path)
+    }
+
+    /// `include "file";` takes every machine of the net in the file.
+    fn parse_include(&mut self, machines: &mut Vec<(Machine, Option<SourceLocation>)>) -> Option<()> {
+        let location = self.location();
+        expect!(self, TokenValue::KwInclude, "expected include");
+        let path = self.parse_file_path()?;
+        let net = self.parse_other_file(&path, &location)?;
+        machines.extend(net.machines.into_iter().map(|m| (m, Some(location.clone()))));
+        Some(())
+    }
+
+    /// `import E, T as U from "file";` takes the listed machines, renaming
+    /// those followed by `as` also where the imported machines call them.
+    fn parse_import(&mut self, machines: &mut Vec<(Machine, Option<SourceLocation>)>) -> Option<()> {
+        let location = self.location();
+        expect!(self, TokenValue::KwImport, "expected import");
+        let mut names: Vec<(char, char, SourceLocation)> = Vec::new();
+        loop {
+            let name_location = self.location();
+            let name = self.parse_machine_name("expected the name of a machine to import")?;
+            let new_name = if accept!(self, TokenValue::KwAs).is_some() {
+                self.parse_machine_name("expected the new name of the machine")?
             } else {
+                name
+            };
+            names.push((name, new_name, name_location));
+            if accept!(self, TokenValue::Comma).is_none() {
+                break;
+            }
+        }
+        expect!(self, TokenValue::KwFrom, "expected from and the file to import from");
+        let path = self.parse_file_path()?;
+        let net = self.parse_other_file(&path, &location)?;
+        for (name, new_name, name_location) in &names {
+            let Some(machine) = net.try_lookup_machine(*name) else {
+                name_location.emit_error(&format!("no machine {} in {}", name, path));
                 return None;
+            };
+            let mut machine = machine.clone();
+            machine.name = *new_name;
+            for t in machine.states.iter_mut().flat_map(|s| &mut s.transitions) {
+                if let Some((_, new, _)) = names.iter().find(|(old, _, _)| *old == t.character) {
+                    t.character = *new;
+                }
             }
+            machines.push((machine, Some(name_location.clone())));
         }
-        expect!(self, TokenValue::RBrace, "expected a list of states");
-        Some(machine)
+        Some(())
     }
 
+    fn parse_machine_name(&mut self, err: &str) -> Option<char> {
+        let name = expect!(self, TokenValue::Ident(name), err, {
+            if !name.is_ascii_uppercase() {
+                self.emit_error("machine name must be ASCII uppercase");
+                return None;
+            }
+            name
+        });
+        Some(name)
+    }
+
+    /// Reports the first machine defined twice, at both definitions and
+    /// at the statements that brought them in.
+    fn check_machine_clashes(machines: &[(Machine, Option<SourceLocation>)]) -> Option<()> {
+        for (i, (m, imported_at)) in machines.iter().enumerate() {
+            let Some((first, first_imported_at)) = machines[..i].iter().find(|(other, _)| other.name == m.name) else {
+                continue;
+            };
+            m.location.emit_error(&format!("machine {} is defined twice", m.name));
+            if let Some(at) = imported_at {
+                at.emit_note(&format!("machine {} taken from another file here", m.name));
+            }
+            first.location.emit_note("first defined here");
+            if let Some(at) = first_imported_at {
+                at.emit_note(&format!("machine {} taken from another file here", m.name));
+            }
+            return None;
+        }
+        Some(())
+    }
+
+    /// Parses a net; machines taken from other files are paired with the
+    /// statement that took them until clashes are checked.
     pub fn parse_mnet(&mut self) -> Option<MachineNet> {
-        let mut machines: Vec<Machine> = Vec::new();
+        let mut machines: Vec<(Machine, Option<SourceLocation>)> = Vec::new();
+        let mut precedence = Precedence::default();
+        let mut axiom = DEFAULT_AXIOM;
+        let mut end_marker = DEFAULT_END_MARKER;
         expect!(self, TokenValue::KwMNet, "expected a machine net");
+        let name = match &self.lookahead {
+            token!(TokenValue::Ident(c)) => Some(c.to_string()),
+            token!(TokenValue::Name(name)) => Some(name.clone()),
+            _ => None
+        };
+        if name.is_some() {
+            self.advance();
+        }
         expect!(self, TokenValue::LBrace, "expected a machine net body enclosed by {}");
-        while let token!(TokenValue::KwMachine) = self.lookahead {
-            if let Some(mach) = self.parse_machine() {
-                machines.push(mach);
-            } else {
-                return None;
+        loop {
+            match self.lookahead {
+                token!(TokenValue::KwMachine) => machines.push((self.parse_machine(&mut precedence)?, None)),
+                token!(TokenValue::KwInclude) => self.parse_include(&mut machines)?,
+                token!(TokenValue::KwImport) => self.parse_import(&mut machines)?,
+                token!(TokenValue::KwLeft) | token!(TokenValue::KwRight) | token!(TokenValue::KwNonAssoc) => self.parse_precedence(&mut precedence)?,
+                token!(TokenValue::KwStart) => axiom = self.parse_axiom()?,
+                token!(TokenValue::KwEnd) => end_marker = self.parse_end_marker()?,
+                _ => break
             }
         }
         expect!(self, TokenValue::RBrace, "unmatched }");
-        Some(MachineNet{machines})
+        Parser::check_machine_clashes(&machines)?;
+        let machines = machines.into_iter().map(|(m, _)| m).collect();
+        Some(MachineNet{name, machines, precedence, axiom, end_marker})
+    }
+
+    /// Parses every net of a file. When there are several, each must have
+    /// a distinct name to be selected by.
+    pub fn parse_file(&mut self) -> Option<Vec<MachineNet>> {
+        let mut nets: Vec<MachineNet> = Vec::new();
+        let mut locations: Vec<SourceLocation> = Vec::new();
+        loop {
+            locations.push(self.location());
+            nets.push(self.parse_mnet()?);
+            if self.lookahead.is_none() {
+                break;
+            }
+        }
+        if nets.len() == 1 {
+            return Some(nets);
+        }
+        for (i, net) in nets.iter().enumerate() {
+            let Some(name) = &net.name else {
+                locations[i].emit_error("a file holding several nets must name each of them");
+                return None;
+            };
+            if let Some(j) = nets[..i].iter().position(|other| other.name.as_ref() == Some(name)) {
+                locations[i].emit_error(&format!("net {} is defined twice", name));
+                locations[j].emit_note("first defined here");
+                return None;
+            }
+        }
+        Some(nets)
     }
 }
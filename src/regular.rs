@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use crate::analysis::NetAnalysis;
+use crate::elr_pilot::*;
+use crate::lexer::SourceLocation;
+
+/// A machine that calls itself with a non-empty string on both sides,
+/// through the call arcs `(machine, state, callee)` listed in order.
+pub struct SelfEmbedding {
+    pub machine: char,
+    pub calls: Vec<(char, i32, char)>
+}
+
+impl SelfEmbedding {
+    pub fn describe(&self, net: &MachineNet) -> String {
+        let arcs: Vec<_> = self.calls.iter().map(|(m, s, callee)| {
+            let dest = net.lookup_state(*m, *s).try_lookup_transition(*callee).unwrap().dest_id;
+            format!("{} -{}-> {}", net.state_label(*m, *s), callee, net.state_label(*m, dest))
+        }).collect();
+        format!("{} embeds itself through {}", self.machine, arcs.join(", "))
+    }
+}
+
+/// Machines whose runs can read at least one terminal. Productivity is
+/// not checked: unproductive arcs count as any other.
+fn nonempty_machines(net: &MachineNet) -> HashSet<char> {
+    let mut res: HashSet<char> = HashSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for m in &net.machines {
+            if !res.contains(&m.name) && m.states.iter().flat_map(|s| &s.transitions).any(|t| !t.is_nonterminal() || res.contains(&t.character)) {
+                res.insert(m.name);
+                changed = true;
+            }
+        }
+    }
+    res
+}
+
+/// States reachable from `from` (forward, or backward when `reverse`)
+/// along a path with at least one arc reading a terminal.
+fn nonempty_reach(machine: &Machine, nonempty: &HashSet<char>, from: &[i32], reverse: bool) -> HashSet<i32> {
+    let mut edges: Vec<(i32, i32, bool)> = Vec::new();
+    for s in &machine.states {
+        for t in &s.transitions {
+            let solid = !t.is_nonterminal() || nonempty.contains(&t.character);
+            if reverse {
+                edges.push((t.dest_id, s.id, solid));
+            } else {
+                edges.push((s.id, t.dest_id, solid));
+            }
+        }
+    }
+    let mut seen: HashSet<(i32, bool)> = from.iter().map(|s| (*s, false)).collect();
+    let mut work: VecDeque<(i32, bool)> = seen.iter().copied().collect();
+    while let Some((s, flag)) = work.pop_front() {
+        for (src, dest, solid) in &edges {
+            if *src == s && seen.insert((*dest, flag || *solid)) {
+                work.push_back((*dest, flag || *solid));
+            }
+        }
+    }
+    seen.into_iter().filter(|(_, flag)| *flag).map(|(s, _)| s).collect()
+}
+
+/// A machine reached through calls, and whether a terminal was read before
+/// and after the calls on the way.
+type CallNode = (char, bool, bool);
+
+/// Machines reachable from the axiom through call arcs.
+pub fn called_machines(net: &MachineNet) -> HashSet<char> {
+    let mut res = HashSet::from([net.axiom]);
+    let mut work = vec![net.axiom];
+    while let Some(m) = work.pop() {
+        for t in net.lookup_machine(m).states.iter().flat_map(|s| &s.transitions) {
+            if t.is_nonterminal() && res.insert(t.character) {
+                work.push(t.character);
+            }
+        }
+    }
+    res
+}
+
+/// The machines reachable from the axiom that are self-embedding, each
+/// with a witness. A net without any generates a regular language.
+pub fn find_self_embeddings(net: &MachineNet) -> Vec<SelfEmbedding> {
+    let nonempty = nonempty_machines(net);
+    let called = called_machines(net);
+    // Call arcs with whether a terminal can be read before and after them.
+    let mut calls: Vec<(char, i32, char, bool, bool)> = Vec::new();
+    for m in net.machines.iter().filter(|m| called.contains(&m.name)) {
+        let finals: Vec<i32> = m.states.iter().filter(|s| s.is_final).map(|s| s.id).collect();
+        let before = nonempty_reach(m, &nonempty, &[m.initial_id()], false);
+        let after = nonempty_reach(m, &nonempty, &finals, true);
+        for s in &m.states {
+            for t in s.transitions.iter().filter(|t| t.is_nonterminal()) {
+                calls.push((m.name, s.id, t.character, before.contains(&s.id), after.contains(&t.dest_id)));
+            }
+        }
+    }
+
+    let mut res: Vec<SelfEmbedding> = Vec::new();
+    let mut machines: Vec<char> = called.into_iter().collect();
+    machines.sort();
+    for a in machines {
+        let start = (a, false, false);
+        let mut parent: HashMap<CallNode, (CallNode, (char, i32, char))> = HashMap::new();
+        let mut work = VecDeque::from([start]);
+        let target = (a, true, true);
+        while let Some(node @ (m, l, r)) = work.pop_front() {
+            if node == target {
+                break;
+            }
+            for (caller, state, callee, before, after) in &calls {
+                let next = (*callee, l || *before, r || *after);
+                if *caller == m && next != start && !parent.contains_key(&next) {
+                    parent.insert(next, (node, (*caller, *state, *callee)));
+                    work.push_back(next);
+                }
+            }
+        }
+        if parent.contains_key(&target) {
+            let mut path: Vec<(char, i32, char)> = Vec::new();
+            let mut node = target;
+            while node != start {
+                let (prev, call) = parent[&node];
+                path.push(call);
+                node = prev;
+            }
+            path.reverse();
+            res.push(SelfEmbedding{machine: a, calls: path});
+        }
+    }
+    res
+}
+
+/// States of a machine reachable from the initial state (forward), or
+/// reaching a final state (backward), through arcs that can read nothing.
+fn empty_reach(machine: &Machine, analysis: &NetAnalysis, reverse: bool) -> HashSet<i32> {
+    let mut res: HashSet<i32> = machine.states.iter().filter(|s| if reverse { s.is_final } else { s.is_initial }).map(|s| s.id).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for s in &machine.states {
+            for t in s.transitions.iter().filter(|t| t.is_nonterminal() && analysis.is_nullable(t.character)) {
+                let (from, to) = if reverse { (t.dest_id, s.id) } else { (s.id, t.dest_id) };
+                if res.contains(&from) && res.insert(to) {
+                    changed = true;
+                }
+            }
+        }
+    }
+    res
+}
+
+/// An automaton with ε-moves (`None`), built by flattening a net.
+struct Nfa {
+    arcs: Vec<Vec<(Option<char>, usize)>>
+}
+
+impl Nfa {
+    fn add_state(&mut self) -> usize {
+        self.arcs.push(Vec::new());
+        self.arcs.len() - 1
+    }
+
+    fn add_arc(&mut self, from: usize, label: Option<char>, to: usize) {
+        self.arcs[from].push((label, to));
+    }
+
+    fn closure(&self, state: usize) -> HashSet<usize> {
+        let mut res = HashSet::from([state]);
+        let mut work = vec![state];
+        while let Some(s) = work.pop() {
+            for (label, dest) in &self.arcs[s] {
+                if label.is_none() && res.insert(*dest) {
+                    work.push(*dest);
+                }
+            }
+        }
+        res
+    }
+
+    /// The same automaton without ε-moves, as a machine named `name` with
+    /// only the states reachable from `start`.
+    fn to_machine(&self, name: char, start: usize, accept: usize) -> Machine {
+        let mut number: HashMap<usize, i32> = HashMap::from([(start, 0)]);
+        let mut order = vec![start];
+        let mut states: Vec<State> = Vec::new();
+        let mut i = 0;
+        while i < order.len() {
+            let closure = self.closure(order[i]);
+            let mut transitions: Vec<Transition> = Vec::new();
+            for (label, dest) in closure.iter().flat_map(|s| &self.arcs[*s]) {
+                let Some(character) = label else { continue };
+                let dest_id = *number.entry(*dest).or_insert_with(|| {
+                    order.push(*dest);
+                    order.len() as i32 - 1
+                });
+                let t = Transition{character: *character, dest_id, location: SourceLocation::default()};
+                if !transitions.contains(&t) {
+                    transitions.push(t);
+                }
+            }
+            states.push(State{
+                id: i as i32,
+                name: i.to_string(),
+                transitions,
+                is_initial: i == 0,
+                is_final: closure.contains(&accept),
+                location: SourceLocation::default()
+            });
+            i += 1;
+        }
+        Machine{name, states, location: SourceLocation::default()}
+    }
+}
+
+/// Substitutes machines into each other. Mutually recursive machines form
+/// a group whose calls inside the group all come first in the caller (left
+/// recursion) or all come last (right recursion), as the net is not
+/// self-embedding; either way the recursion becomes a loop.
+struct Flattener<'a> {
+    net: &'a MachineNet,
+    nfa: Nfa,
+    groups: HashMap<char, HashSet<char>>,
+    empty_prefix: HashMap<char, HashSet<i32>>,
+    empty_suffix: HashMap<char, HashSet<i32>>,
+    /// States that can be reached after reading at least one terminal.
+    read_before: HashMap<char, HashSet<i32>>
+}
+
+impl<'a> Flattener<'a> {
+    fn new(net: &'a MachineNet) -> Flattener<'a> {
+        let analysis = NetAnalysis::new(net);
+        let mut reach: HashMap<char, HashSet<char>> = HashMap::new();
+        for m in &net.machines {
+            let mut seen: HashSet<char> = HashSet::new();
+            let mut work = vec![m.name];
+            while let Some(x) = work.pop() {
+                for t in net.lookup_machine(x).states.iter().flat_map(|s| &s.transitions) {
+                    if t.is_nonterminal() && seen.insert(t.character) {
+                        work.push(t.character);
+                    }
+                }
+            }
+            reach.insert(m.name, seen);
+        }
+        let nonempty = nonempty_machines(net);
+        let groups = net.machines.iter().map(|m| {
+            let mut group: HashSet<char> = reach[&m.name].iter().copied().filter(|x| reach[x].contains(&m.name)).collect();
+            group.insert(m.name);
+            (m.name, group)
+        }).collect();
+        Flattener{
+            net,
+            nfa: Nfa{arcs: Vec::new()},
+            groups,
+            empty_prefix: net.machines.iter().map(|m| (m.name, empty_reach(m, &analysis, false))).collect(),
+            empty_suffix: net.machines.iter().map(|m| (m.name, empty_reach(m, &analysis, true))).collect(),
+            read_before: net.machines.iter().map(|m| (m.name, nonempty_reach(m, &nonempty, &[m.initial_id()], false))).collect()
+        }
+    }
+
+    /// A fresh copy of the machine with everything it calls substituted,
+    /// as its start and accepting states.
+    fn fragment(&mut self, machine: char) -> (usize, usize) {
+        let net = self.net;
+        let mut group: Vec<char> = self.groups[&machine].iter().copied().collect();
+        group.sort();
+        let inner_calls: Vec<(char, i32, &Transition)> = group.iter().flat_map(|m| {
+            net.lookup_machine(*m).states.iter().flat_map(move |s| s.transitions.iter().map(move |t| (*m, s.id, t)))
+        }).filter(|(_, _, t)| group.contains(&t.character)).collect();
+        // Without self-embedding, either no inner call can be preceded by a
+        // terminal (left recursion) or none can be followed by one.
+        let right = inner_calls.iter().any(|(m, s, _)| self.read_before[m].contains(s));
+
+        let start = self.nfa.add_state();
+        let accept = self.nfa.add_state();
+        let mut ids: HashMap<(char, i32), usize> = HashMap::new();
+        for m in &group {
+            for s in &net.lookup_machine(*m).states {
+                ids.insert((*m, s.id), self.nfa.add_state());
+            }
+        }
+        let finals = |m: char| net.lookup_machine(m).states.iter().filter(|s| s.is_final).map(move |s| (m, s.id));
+        for m in &group {
+            for s in &net.lookup_machine(*m).states {
+                for t in &s.transitions {
+                    let (from, to) = (ids[&(*m, s.id)], ids[&(*m, t.dest_id)]);
+                    if !t.is_nonterminal() {
+                        self.nfa.add_arc(from, Some(t.character), to);
+                    } else if !group.contains(&t.character) {
+                        let (callee_start, callee_accept) = self.fragment(t.character);
+                        self.nfa.add_arc(from, None, callee_start);
+                        self.nfa.add_arc(callee_accept, None, to);
+                    } else if right {
+                        // Nothing is read after the call: enter the callee, and
+                        // resume the caller, whichever it was, once it is done.
+                        if self.empty_suffix[m].contains(&t.dest_id) {
+                            self.nfa.add_arc(from, None, ids[&(t.character, net.lookup_machine(t.character).initial_id())]);
+                            for f in finals(t.character) {
+                                self.nfa.add_arc(ids[&f], None, to);
+                            }
+                        }
+                    } else if self.empty_prefix[m].contains(&s.id) {
+                        // Nothing is read before the call: the callee ran first, resume the caller.
+                        for f in finals(t.character) {
+                            self.nfa.add_arc(ids[&f], None, to);
+                        }
+                    }
+                }
+            }
+        }
+        if right {
+            self.nfa.add_arc(start, None, ids[&(machine, net.lookup_machine(machine).initial_id())]);
+        } else {
+            for m in &group {
+                self.nfa.add_arc(start, None, ids[&(*m, net.lookup_machine(*m).initial_id())]);
+            }
+        }
+        for f in finals(machine) {
+            self.nfa.add_arc(ids[&f], None, accept);
+        }
+        (start, accept)
+    }
+}
+
+/// A single machine over terminals accepting the language of the net,
+/// built by substituting the machines into each other from the axiom, or
+/// the self-embeddings that make this impossible. The result is usually
+/// nondeterministic; see `Machine::minimize`.
+pub fn flatten(net: &MachineNet) -> Result<Machine, Vec<SelfEmbedding>> {
+    let found = find_self_embeddings(net);
+    if !found.is_empty() {
+        return Err(found);
+    }
+    let mut flattener = Flattener::new(net);
+    let (start, accept) = flattener.fragment(net.axiom);
+    Ok(flattener.nfa.to_machine(net.axiom, start, accept))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use crate::sentences::LanguageSlices;
+    use super::*;
+
+    #[test]
+    fn test_self_embedding() {
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        let found = find_self_embeddings(&net);
+        assert!(found.iter().any(|e| e.machine == 'B'));
+        let net = load_net(r"./tests/expr_prec.txt").unwrap();
+        assert_eq!(find_self_embeddings(&net)[0].calls, vec![('S', 0, 'S'), ('S', 3, 'S')]);
+        let net = load_net(r"./tests/lookahead_context.txt").unwrap();
+        assert!(find_self_embeddings(&net).is_empty());
+    }
+
+    /// Every word up to `len` terminals is accepted by the flattened net
+    /// exactly when the net generates it.
+    fn assert_same_language(path: &str, len: usize) {
+        let net = load_net(path).unwrap();
+        let flat = flatten(&net).ok().unwrap();
+        let sentences: HashSet<String> = LanguageSlices::new(&net, len).sentences().into_iter().collect();
+        let mut terminals: Vec<char> = net.machines.iter().flat_map(|m| &m.states).flat_map(|s| &s.transitions).map(|t| t.character).filter(|c| !c.is_ascii_uppercase()).collect();
+        terminals.sort();
+        terminals.dedup();
+        let mut words = vec![String::new()];
+        for _ in 0..len {
+            let longer: Vec<String> = words.iter().flat_map(|w| terminals.iter().map(move |c| format!("{}{}", w, c))).collect();
+            for w in &words {
+                assert_eq!(flat.accepts(w), sentences.contains(w), "{} on {:?}", path, w);
+            }
+            words = longer;
+        }
+    }
+
+    #[test]
+    fn test_flatten_left_and_right_recursion() {
+        assert_same_language(r"./tests/left_recursion.txt", 6);
+        assert_same_language(r"./tests/right_recursion.txt", 6);
+        assert_same_language(r"./tests/linear_recursion.txt", 6);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let net = load_net(r"./tests/linear_recursion.txt").unwrap();
+        let flat = flatten(&net).ok().unwrap();
+        for sentence in LanguageSlices::new(&net, 6).sentences() {
+            assert!(flat.accepts(&sentence));
+        }
+        let min = flat.minimize();
+        assert_eq!(min.states.len(), 2);
+        assert!(min.accepts("bbcaa") && min.accepts("c"));
+        assert!(!min.accepts("cb") && !min.accepts("bb"));
+        let net = load_net(r"./tests/expr_prec.txt").unwrap();
+        assert!(flatten(&net).is_err());
+    }
+}
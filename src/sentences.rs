@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use crate::analysis::shortest_strings;
+use crate::elr_pilot::*;
+
+/// The strings of each length up to a bound that lead every machine state
+/// to a final state of its machine.
+pub struct LanguageSlices {
+    /// Initial state of the axiom.
+    start: (char, i32),
+    slices: Vec<HashMap<(char, i32), HashSet<String>>>
+}
+
+impl LanguageSlices {
+    pub fn new(net: &MachineNet, max_len: usize) -> LanguageSlices {
+        let mut res = LanguageSlices{start: (net.axiom, net.initial_id(net.axiom)), slices: Vec::new()};
+        for _ in 0..=max_len {
+            res.grow(net);
+        }
+        res
+    }
+
+    /// Extends the bound by one terminal.
+    pub fn grow(&mut self, net: &MachineNet) {
+        let slice = self.compute_slice(net, self.slices.len());
+        self.slices.push(slice);
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.slices.len() - 1
+    }
+
+    pub fn strings(&self, machine: char, id: i32, len: usize) -> &HashSet<String> {
+        &self.slices[len][&(machine, id)]
+    }
+
+    /// Sentences of the axiom up to the bound, shortest first and in
+    /// lexicographic order within the same length.
+    pub fn sentences(&self) -> Vec<String> {
+        let mut res: Vec<String> = Vec::new();
+        for n in 0..=self.max_len() {
+            res.extend(self.sentences_of_length(n));
+        }
+        res
+    }
+
+    pub fn sentences_of_length(&self, len: usize) -> Vec<String> {
+        let mut res: Vec<_> = self.strings(self.start.0, self.start.1, len).iter().cloned().collect();
+        res.sort();
+        res
+    }
+
+    fn compute_slice(&self, net: &MachineNet, n: usize) -> HashMap<(char, i32), HashSet<String>> {
+        let mut cur: HashMap<(char, i32), HashSet<String>> = HashMap::new();
+        for m in &net.machines {
+            for s in &m.states {
+                let init = if n == 0 && s.is_final { HashSet::from([String::new()]) } else { HashSet::new() };
+                cur.insert((m.name, s.id), init);
+            }
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for m in &net.machines {
+                for s in &m.states {
+                    let mut new: HashSet<String> = HashSet::new();
+                    for t in &s.transitions {
+                        if !t.is_nonterminal() {
+                            if n > 0 {
+                                for w in &self.slices[n - 1][&(m.name, t.dest_id)] {
+                                    new.insert(format!("{}{}", t.character, w));
+                                }
+                            }
+                            continue;
+                        }
+                        for k in 0..=n {
+                            let entry = (t.character, net.initial_id(t.character));
+                            let left = if k == n { &cur[&entry] } else { &self.slices[k][&entry] };
+                            let right = if k == 0 { &cur[&(m.name, t.dest_id)] } else { &self.slices[n - k][&(m.name, t.dest_id)] };
+                            for l in left {
+                                for r in right {
+                                    new.insert(format!("{}{}", l, r));
+                                }
+                            }
+                        }
+                    }
+                    let old = cur.get_mut(&(m.name, s.id)).unwrap();
+                    let old_len = old.len();
+                    old.extend(new);
+                    changed |= old.len() != old_len;
+                }
+            }
+        }
+        cur
+    }
+}
+
+/// The `count` shortest sentences of the net, in order of length. The
+/// search stops early when no sentence longer than `max_len` is needed.
+pub fn shortest_sentences(net: &MachineNet, count: usize, max_len: usize) -> Vec<String> {
+    let mut slices = LanguageSlices::new(net, 0);
+    let mut res = slices.sentences_of_length(0);
+    while res.len() < count && slices.max_len() < max_len {
+        slices.grow(net);
+        res.extend(slices.sentences_of_length(slices.max_len()));
+    }
+    res.truncate(count);
+    res
+}
+
+/// Small deterministic generator (SplitMix64), good enough for sampling
+/// exercises and fuzz inputs without external dependencies.
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng{state: seed}
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Random sentence generator aiming at a target length. Each move is
+/// weighted by how much room its shortest completion leaves before the
+/// target, counting the shortest completions still owed to the calling
+/// machines; stopping in a final state has unit weight. Once the target is
+/// reached (or the recursion gets too deep) every machine is closed along
+/// its shortest completion, so sampling terminates on any recursive net.
+pub struct SentenceSampler<'a> {
+    net: &'a MachineNet,
+    strings: HashMap<(char, i32), String>,
+    rng: Rng,
+    target: usize,
+    steps: usize
+}
+
+enum Move {
+    Stop,
+    Shift(char, i32)
+}
+
+impl<'a> SentenceSampler<'a> {
+    pub fn new(net: &'a MachineNet, seed: u64, target: usize) -> SentenceSampler<'a> {
+        SentenceSampler{net, strings: shortest_strings(net), rng: Rng::new(seed), target, steps: 0}
+    }
+
+    /// Returns `None` when the axiom generates no sentence at all.
+    pub fn sample(&mut self) -> Option<String> {
+        let axiom = self.net.axiom;
+        self.strings.get(&(axiom, self.net.initial_id(axiom)))?;
+        let mut out = String::new();
+        self.steps = 0;
+        self.sample_machine(axiom, 0, 0, &mut out);
+        Some(out)
+    }
+
+    fn min_len(&self, machine: char, mv: &Move) -> Option<usize> {
+        match mv {
+            Move::Stop => Some(0),
+            Move::Shift(character, dest_id) => {
+                let head = if character.is_ascii_uppercase() {
+                    self.strings.get(&(*character, self.net.initial_id(*character)))?.len()
+                } else {
+                    1
+                };
+                Some(head + self.strings.get(&(machine, *dest_id))?.len())
+            }
+        }
+    }
+
+    /// Runs `machine` from its initial state; `pending` is the length of
+    /// the shortest completions of the callers.
+    fn sample_machine(&mut self, machine: char, depth: usize, pending: usize, out: &mut String) {
+        let mut id = self.net.initial_id(machine);
+        loop {
+            self.steps += 1;
+            let too_long = out.len() + pending >= self.target;
+            let too_deep = depth > self.target + MAX_EXTRA_DEPTH;
+            if too_long || too_deep || self.steps > MAX_STEPS_PER_SYMBOL * (self.target + 1) {
+                out.push_str(&self.strings[&(machine, id)]);
+                return;
+            }
+            let remaining = self.target - out.len() - pending;
+            let state = self.net.lookup_state(machine, id);
+            let mut moves: Vec<(Move, usize)> = Vec::new();
+            if state.is_final {
+                moves.push((Move::Stop, 1));
+            }
+            for t in &state.transitions {
+                let mv = Move::Shift(t.character, t.dest_id);
+                if let Some(len) = self.min_len(machine, &mv) {
+                    if len <= remaining {
+                        moves.push((mv, remaining - len + 1));
+                    }
+                }
+            }
+            if moves.is_empty() {
+                out.push_str(&self.strings[&(machine, id)]);
+                return;
+            }
+            let total: usize = moves.iter().map(|(_, w)| w).sum();
+            let mut pick = self.rng.below(total);
+            let mut chosen = 0;
+            while pick >= moves[chosen].1 {
+                pick -= moves[chosen].1;
+                chosen += 1;
+            }
+            match moves[chosen].0 {
+                Move::Stop => return,
+                Move::Shift(character, dest_id) => {
+                    if character.is_ascii_uppercase() {
+                        let rest = self.strings[&(machine, dest_id)].len();
+                        self.sample_machine(character, depth + 1, pending + rest, out);
+                    } else {
+                        out.push(character);
+                    }
+                    id = dest_id;
+                }
+            }
+        }
+    }
+}
+
+/// Nesting allowed beyond the target length before machines are closed.
+const MAX_EXTRA_DEPTH: usize = 8;
+
+/// Bound on the moves spent per target symbol, which stops loops over
+/// nullable nonterminals that never grow the sentence.
+const MAX_STEPS_PER_SYMBOL: usize = 64;
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use super::*;
+
+    #[test]
+    fn test_shortest_sentences() {
+        let net = load_net(r"./tests/elr_mnet_2024-06-13.txt").unwrap();
+        assert_eq!(shortest_sentences(&net, 4, 10), vec!["", "db", "dcabb", "dcaabb"]);
+    }
+
+    #[test]
+    fn test_sample_is_sentence() {
+        let net = load_net(r"./tests/dangling_else.txt").unwrap();
+        let mut sampler = SentenceSampler::new(&net, 42, 12);
+        let sentences = LanguageSlices::new(&net, 20).sentences();
+        for _ in 0..20 {
+            let s = sampler.sample().unwrap();
+            assert!(sentences.contains(&s));
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt;
+
+/// Syntax tree of a derivation through a machine net. Every inner node
+/// corresponds to one run of the machine it is named after.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub enum SyntaxTree {
+    Leaf(char),
+    Node(char, Vec<SyntaxTree>)
+}
+
+impl SyntaxTree {
+    pub fn frontier(&self) -> String {
+        match self {
+            SyntaxTree::Leaf(c) => c.to_string(),
+            SyntaxTree::Node(_, children) => children.iter().map(|c| c.frontier()).collect()
+        }
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxTree::Leaf(c) => write!(f, "{}", c),
+            SyntaxTree::Node(name, children) => {
+                let children: Vec<_> = children.iter().map(|c| c.to_string()).collect();
+                write!(f, "{}({})", name, children.join(" "))
+            }
+        }
+    }
+}
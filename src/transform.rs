@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use crate::elr_pilot::*;
+use crate::regular::called_machines;
+
+/// The net produced by a pass, and what the pass changed.
+pub struct Transformed {
+    pub net: MachineNet,
+    pub changes: Vec<String>
+}
+
+fn validated(net: MachineNet, changes: Vec<String>) -> Option<Transformed> {
+    if net.validate() {
+        Some(Transformed{net, changes})
+    } else {
+        None
+    }
+}
+
+/// Removes the machines never called from the axiom, and from the others
+/// the states that are unreachable or cannot reach a final state, with
+/// the arcs leading to them.
+pub fn trim(net: &MachineNet) -> Option<Transformed> {
+    let called = called_machines(net);
+    let mut res = net.clone();
+    let mut changes: Vec<String> = Vec::new();
+    for m in res.machines.iter().filter(|m| !called.contains(&m.name)) {
+        changes.push(format!("removed machine {}, never called from {}", m.name, net.axiom));
+    }
+    res.machines.retain(|m| called.contains(&m.name));
+    res.precedence.machines.retain(|m, _| called.contains(m));
+    for m in &mut res.machines {
+        let reachable = m.reachable();
+        let coreachable = m.coreachable();
+        for s in &m.states {
+            if !reachable.contains(&s.id) {
+                changes.push(format!("removed state {}{}, unreachable", s.name, m.name));
+            } else if !coreachable.contains(&s.id) {
+                changes.push(format!("removed state {}{}, no final state can be reached from it", s.name, m.name));
+            }
+        }
+        m.states.retain(|s| reachable.contains(&s.id) && coreachable.contains(&s.id));
+        for s in &mut m.states {
+            s.transitions.retain(|t| reachable.contains(&t.dest_id) && coreachable.contains(&t.dest_id));
+        }
+    }
+    validated(res, changes)
+}
+
+/// Numbers the states of every machine from 0 in breadth-first order from
+/// the initial state, following the arcs in the order they are declared.
+/// Unreachable states come last, in their original order. The states are
+/// named after their new numbers.
+pub fn renumber(net: &MachineNet) -> Option<Transformed> {
+    let mut res = net.clone();
+    let mut changes: Vec<String> = Vec::new();
+    for m in &mut res.machines {
+        let initial = m.initial_id();
+        let mut order: Vec<i32> = vec![initial];
+        let mut work = VecDeque::from([initial]);
+        while let Some(id) = work.pop_front() {
+            for t in &m.lookup_state(id).transitions {
+                if !order.contains(&t.dest_id) {
+                    order.push(t.dest_id);
+                    work.push_back(t.dest_id);
+                }
+            }
+        }
+        order.extend(m.states.iter().map(|s| s.id).filter(|id| !order.contains(id)).collect::<Vec<_>>());
+        let number: HashMap<i32, i32> = order.iter().enumerate().map(|(i, id)| (*id, i as i32)).collect();
+        for id in &order {
+            let name = m.state_name(*id);
+            if name != number[id].to_string() {
+                changes.push(format!("renumbered state {}{} to {}{}", name, m.name, number[id], m.name));
+            }
+        }
+        for s in &mut m.states {
+            s.id = number[&s.id];
+            s.name = s.id.to_string();
+            for t in &mut s.transitions {
+                t.dest_id = number[&t.dest_id];
+            }
+        }
+        m.states.sort_by_key(|s| s.id);
+    }
+    validated(res, changes)
+}
+
+/// Gives every machine whose initial state has incoming arcs a new initial
+/// state with the same outgoing arcs and finality, which takes over the id
+/// and name of the old one. The old initial state takes the next free
+/// number and keeps the incoming arcs.
+pub fn normalize_initial(net: &MachineNet) -> Option<Transformed> {
+    let mut res = net.clone();
+    let mut changes: Vec<String> = Vec::new();
+    for m in &mut res.machines {
+        let initial_id = m.initial_id();
+        if !m.states.iter().flat_map(|s| &s.transitions).any(|t| t.dest_id == initial_id) {
+            continue;
+        }
+        let old = m.fresh_id();
+        let mut initial = m.initial_state().clone();
+        let pos = m.states.iter().position(|s| s.id == initial_id).unwrap();
+        for s in &mut m.states {
+            if s.id == initial_id {
+                s.id = old;
+                s.name = old.to_string();
+                s.is_initial = false;
+            }
+            for t in s.transitions.iter_mut().filter(|t| t.dest_id == initial_id) {
+                t.dest_id = old;
+            }
+        }
+        initial.transitions = m.lookup_state(old).transitions.clone();
+        m.states.insert(pos, initial);
+        changes.push(format!("state {}{} has incoming arcs, moved it to {}{} and added a new initial state", m.state_name(initial_id), m.name, old, m.name));
+    }
+    validated(res, changes)
+}
+
+/// Machines called, directly or not, by `machine`.
+fn reached_machines(net: &MachineNet, machine: char) -> HashSet<char> {
+    let mut res: HashSet<char> = HashSet::new();
+    let mut work = vec![machine];
+    while let Some(m) = work.pop() {
+        for t in net.lookup_machine(m).states.iter().flat_map(|s| &s.transitions) {
+            if t.is_nonterminal() && res.insert(t.character) {
+                work.push(t.character);
+            }
+        }
+    }
+    res
+}
+
+/// `machine` with a copy of `callee` substituted at every call arc. The
+/// copies are joined by ε-moves that are then removed, and the copied
+/// states left unreachable are dropped. When a copy shares a label with
+/// an arc leaving the same state, the result is determinized.
+fn inline_into(machine: &Machine, callee: &Machine, changes: &mut Vec<String>) -> Machine {
+    let mut next_id = machine.fresh_id();
+    let mut states: Vec<State> = machine.states.iter().map(|s| State{transitions: vec![], ..s.clone()}).collect();
+    let mut arcs: Vec<(i32, Option<Transition>, i32)> = Vec::new();
+    for s in &machine.states {
+        for t in &s.transitions {
+            if t.character != callee.name {
+                arcs.push((s.id, Some(t.clone()), t.dest_id));
+                continue;
+            }
+            let copy = |id: i32| next_id + callee.states.iter().position(|c| c.id == id).unwrap() as i32;
+            for c in &callee.states {
+                states.push(State{id: copy(c.id), name: copy(c.id).to_string(), transitions: vec![], is_initial: false, is_final: false, location: c.location.clone()});
+                for ct in &c.transitions {
+                    arcs.push((copy(c.id), Some(Transition{dest_id: copy(ct.dest_id), ..ct.clone()}), copy(ct.dest_id)));
+                }
+                if c.is_final {
+                    arcs.push((copy(c.id), None, t.dest_id));
+                }
+            }
+            arcs.push((s.id, None, copy(callee.initial_id())));
+            changes.push(format!("inlined {} at arc {}{} -{}-> {}{} as states {}{} to {}{}", callee.name, s.name, machine.name, callee.name, machine.state_name(t.dest_id), machine.name, next_id, machine.name, next_id + callee.states.len() as i32 - 1, machine.name));
+            next_id += callee.states.len() as i32;
+        }
+    }
+
+    let finals: HashSet<i32> = machine.states.iter().filter(|s| s.is_final).map(|s| s.id).collect();
+    for s in &mut states {
+        let mut closure = vec![s.id];
+        let mut i = 0;
+        while i < closure.len() {
+            for (from, label, to) in &arcs {
+                if *from == closure[i] && label.is_none() && !closure.contains(to) {
+                    closure.push(*to);
+                }
+            }
+            i += 1;
+        }
+        s.is_final = closure.iter().any(|id| finals.contains(id));
+        for (_, label, _) in arcs.iter().filter(|(from, _, _)| closure.contains(from)) {
+            if let Some(t) = label {
+                if !s.transitions.iter().any(|x| x.character == t.character && x.dest_id == t.dest_id) {
+                    s.transitions.push(t.clone());
+                }
+            }
+        }
+    }
+    let mut res = Machine{name: machine.name, states, location: machine.location.clone()};
+    let reachable = res.reachable();
+    let copied = |id: i32| machine.try_lookup_state(id).is_none();
+    for s in res.states.iter().filter(|s| copied(s.id) && !reachable.contains(&s.id)) {
+        changes.push(format!("removed state {}{}, unreachable after inlining", s.name, machine.name));
+    }
+    res.states.retain(|s| !copied(s.id) || reachable.contains(&s.id));
+    if !res.is_deterministic() {
+        res = res.determinize();
+        changes.push(format!("determinized machine {}, inlining {} gave a state two arcs with the same label", machine.name, callee.name));
+    }
+    res
+}
+
+/// Substitutes the non-recursive `machine` into every machine calling it,
+/// then removes it unless it is the axiom.
+pub fn inline_machine(net: &MachineNet, machine: char) -> Option<Transformed> {
+    let Some(callee) = net.try_lookup_machine(machine) else {
+        eprintln!("error: machine {} does not exist", machine);
+        return None;
+    };
+    if reached_machines(net, machine).contains(&machine) {
+        eprintln!("error: machine {} is recursive and cannot be inlined", machine);
+        return None;
+    }
+    let mut changes: Vec<String> = Vec::new();
+    let mut res = net.clone();
+    for m in &mut res.machines {
+        if m.name != machine && m.states.iter().flat_map(|s| &s.transitions).any(|t| t.character == machine) {
+            *m = inline_into(m, callee, &mut changes);
+        }
+    }
+    if machine != net.axiom {
+        res.machines.retain(|m| m.name != machine);
+        changes.push(format!("removed machine {}, no longer called", machine));
+        if res.precedence.machines.remove(&machine).is_some() {
+            changes.push(format!("dropped the %prec of machine {}", machine));
+        }
+    }
+    validated(res, changes)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_net;
+    use crate::sentences::LanguageSlices;
+    use super::*;
+
+    fn sentences(net: &MachineNet) -> Vec<String> {
+        let mut res = LanguageSlices::new(net, 6).sentences();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn test_trim_and_renumber() {
+        let net = load_net(r"./tests/untidy.txt").unwrap();
+        let trimmed = trim(&net).unwrap();
+        assert_eq!(trimmed.changes.len(), 3);
+        assert!(trimmed.net.try_lookup_machine('U').is_none());
+        let ids: Vec<i32> = trimmed.net.lookup_machine('S').states.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+        let renumbered = renumber(&trimmed.net).unwrap();
+        assert_eq!(renumbered.changes, vec!["renumbered state 2S to 1S", "renumbered state 1S to 2S"]);
+        assert_eq!(renumbered.net.lookup_state('S', 0).try_lookup_transition('a').unwrap().dest_id, 1);
+        assert_eq!(sentences(&renumbered.net), sentences(&net));
+    }
+
+    #[test]
+    fn test_normalize_and_inline() {
+        let net = load_net(r"./tests/untidy.txt").unwrap();
+        let normalized = normalize_initial(&net).unwrap();
+        assert_eq!(normalized.changes.len(), 1);
+        assert!(normalized.net.lookup_machine('S').states.iter().flat_map(|s| &s.transitions).all(|t| t.dest_id != 0));
+        assert_eq!(sentences(&normalized.net), sentences(&net));
+        let inlined = inline_machine(&net, 'A').unwrap();
+        assert!(inlined.net.try_lookup_machine('A').is_none());
+        assert_eq!(sentences(&inlined.net), sentences(&net));
+        let net = load_net(r"./tests/expr_prec.txt").unwrap();
+        assert!(inline_machine(&net, 'S').is_none());
+    }
+
+    #[test]
+    fn test_inline_shared_label() {
+        // Both 0S and the copy of 0A read a, so 0S gets two arcs on a
+        let net = load_net(r"./tests/inline_shared_label.txt").unwrap();
+        let inlined = inline_machine(&net, 'A').unwrap();
+        assert!(inlined.net.lookup_machine('S').is_deterministic());
+        assert!(inlined.changes.iter().any(|c| c.starts_with("determinized machine S")));
+        assert_eq!(sentences(&inlined.net), sentences(&net));
+    }
+}